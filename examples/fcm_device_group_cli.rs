@@ -1,20 +1,17 @@
-use std::path::PathBuf;
-
 use clap::{Parser, Subcommand};
 use fcm_device_group::{
-    FCMDeviceGroup, FCMDeviceGroupClient, FIREBASE_NOTIFICATION_URL, Operation,
+    FCMDeviceGroup, FCMDeviceGroupClient, FIREBASE_NOTIFICATION_URL, NotificationKey,
+    NotificationKeyName, Operation, RegistrationToken, auth::DefaultCredentials,
 };
 use reqwest::Url;
-use yup_oauth2::ServiceAccountAuthenticator;
 
 #[derive(Debug, Parser)]
 struct Args {
-    #[arg(long, env = "GOOGLE_APPLICATION_CREDENTIALS")]
-    auth_file: PathBuf,
-    #[arg(long)]
-    sender_id: String,
     #[arg(long, default_value =FIREBASE_NOTIFICATION_URL)]
     url: Url,
+    /// Sender id (project number) to use instead of the one found with the credentials
+    #[arg(long)]
+    sender_id: Option<String>,
     #[command(subcommand)]
     operation: DeviceGroupOperation,
 }
@@ -25,9 +22,9 @@ pub enum DeviceGroupOperation {
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#creating_a_device_group>
     Create {
         /// Name of the dev device group
-        notification_key_name: String,
+        notification_key_name: NotificationKeyName,
         /// registration IDS to create the device group with
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
     /// Add a devices to the device group
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#adding_and_removing_devices_from_a_device_group>
@@ -36,11 +33,11 @@ pub enum DeviceGroupOperation {
         /// notification_key_name is not required for adding/removing registration tokens, but including it protects you against
         /// accidentally using the incorrect notification_key.
         #[arg(long)]
-        notification_key_name: Option<String>,
+        notification_key_name: Option<NotificationKeyName>,
         /// Device group notification key
-        notification_key: String,
+        notification_key: NotificationKey,
         /// Registration IDS to add
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
     /// Remove a device from a device group
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#adding_and_removing_devices_from_a_device_group>
@@ -49,14 +46,14 @@ pub enum DeviceGroupOperation {
         /// notification_key_name is not required for adding/removing registration tokens, but including it protects you against
         /// accidentally using the incorrect notification_key.
         #[arg(long)]
-        notification_key_name: Option<String>,
+        notification_key_name: Option<NotificationKeyName>,
         /// Device group notification key
-        notification_key: String,
+        notification_key: NotificationKey,
         /// Registration IDS to add
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
     GetKey {
-        name: String,
+        name: NotificationKeyName,
     },
 }
 
@@ -66,15 +63,13 @@ async fn main() {
 
     let args = Args::parse();
 
-    let secret = tokio::fs::read(args.auth_file).await.unwrap();
-    let secret = yup_oauth2::parse_service_account_key(secret).unwrap();
-
-    let auth = ServiceAccountAuthenticator::builder(secret)
-        .build()
-        .await
-        .unwrap();
-
-    let fcm_client = FCMDeviceGroupClient::with_url(args.url, &args.sender_id, auth).unwrap();
+    let builder = match args.sender_id {
+        Some(sender_id) => {
+            FCMDeviceGroupClient::builder(&sender_id, DefaultCredentials::find().await.unwrap())
+        }
+        None => FCMDeviceGroupClient::builder_from_env().await.unwrap(),
+    };
+    let fcm_client = builder.url(args.url).build().unwrap();
 
     log::info!("Running Request");
     match args.operation {
@@ -149,7 +144,11 @@ async fn main() {
         }
         DeviceGroupOperation::GetKey { name } => {
             let device_group = fcm_client.get_key(name).await.unwrap();
-            println!("Device group {:?}", device_group);
+            // Debug output redacts the key, so print it with Display
+            println!(
+                "Device group {} has notification key {}",
+                device_group.notification_key_name, device_group.notification_key
+            );
             return;
         }
     };
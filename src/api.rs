@@ -0,0 +1,92 @@
+use std::future::Future;
+
+use crate::{
+    FCMDeviceGroup, FCMDeviceGroupClient, NotificationKeyName, Operation, OperationResponse,
+    RegistrationToken,
+    error::{
+        FCMDeviceGroupsBadRequest,
+        operation_errors::{
+            ChangeGroupMembersError, CreateGroupError, GetKeyError, OperationResult,
+        },
+    },
+};
+
+/// The device group operations of [`FCMDeviceGroupClient`].
+///
+/// Application code can depend on this trait instead of the client so it can be tested against a mock, such as
+/// `mock::MockDeviceGroupApi` with the `mock` feature, or run against another backend.
+pub trait DeviceGroupApi: Send + Sync {
+    /// Apply the given operation. See [`FCMDeviceGroupClient::apply`].
+    fn apply(
+        &self,
+        operation: Operation,
+    ) -> impl Future<Output = OperationResult<OperationResponse, FCMDeviceGroupsBadRequest>> + Send;
+
+    /// Create a new group. See [`FCMDeviceGroupClient::create_group`].
+    fn create_group(
+        &self,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, CreateGroupError>> + Send;
+
+    /// Add registration ids to a group. See [`FCMDeviceGroupClient::add_to_group`].
+    fn add_to_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send;
+
+    /// Remove registration ids from a group. See [`FCMDeviceGroupClient::remove_from_group`].
+    fn remove_from_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send;
+
+    /// Look up the notification key of a group. See [`FCMDeviceGroupClient::get_key`].
+    fn get_key(
+        &self,
+        notification_key_name: NotificationKeyName,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, GetKeyError>> + Send;
+}
+
+impl DeviceGroupApi for FCMDeviceGroupClient {
+    fn apply(
+        &self,
+        operation: Operation,
+    ) -> impl Future<Output = OperationResult<OperationResponse, FCMDeviceGroupsBadRequest>> + Send
+    {
+        FCMDeviceGroupClient::apply(self, operation)
+    }
+
+    fn create_group(
+        &self,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, CreateGroupError>> + Send {
+        FCMDeviceGroupClient::create_group(self, notification_key_name, registration_ids)
+    }
+
+    fn add_to_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send {
+        FCMDeviceGroupClient::add_to_group(self, group, registration_ids)
+    }
+
+    fn remove_from_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send {
+        FCMDeviceGroupClient::remove_from_group(self, group, registration_ids)
+    }
+
+    fn get_key(
+        &self,
+        notification_key_name: NotificationKeyName,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, GetKeyError>> + Send {
+        FCMDeviceGroupClient::get_key(self, notification_key_name)
+    }
+}
@@ -0,0 +1,182 @@
+//! Token providers for authenticating with FCM.
+//!
+//! The client asks its [`GetToken`] for a token before every request. [`CachedToken`] wraps a provider that is
+//! slow or rate limited, such as one that calls a remote endpoint, so that it is only asked again when the
+//! cached token is about to expire.
+//!
+//! [`MetadataServerAuth`] gets tokens for the service account of a workload running on Google Cloud and
+//! [`AuthorizedUserAuth`] for a user signed in with gcloud. With the `service-account` feature
+//! `ServiceAccountAuth` gets tokens with a Google service account key, without depending on an OAuth library.
+//! [`DefaultCredentials`] picks one of them the way Google's client libraries find Application Default
+//! Credentials.
+use std::{collections::HashMap, future::Future, sync::Arc, time::Duration};
+
+use google_apis_common::GetToken;
+use tokio::{sync::Mutex, time::Instant};
+
+mod authorized_user;
+mod default_credentials;
+mod metadata;
+#[cfg(feature = "service-account")]
+mod service_account;
+
+pub use authorized_user::{AuthorizedUserAuth, AuthorizedUserCredentials};
+pub use default_credentials::{
+    CREDENTIALS_ENV, CredentialsSource, DefaultCredentials, SENDER_ID_ENV,
+};
+pub use metadata::{METADATA_HOST, METADATA_HOST_ENV, MetadataServerAuth};
+#[cfg(feature = "service-account")]
+pub use service_account::{ServiceAccountAuth, ServiceAccountKey};
+
+type GetTokenError = Box<dyn std::error::Error + Send + Sync>;
+type GetTokenResult = Result<Option<String>, GetTokenError>;
+
+/// Token URI of Google's OAuth 2.0 server
+pub const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// Default time before expiry at which a token is refreshed
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// Successful response of an OAuth 2.0 token endpoint
+#[derive(serde::Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+fn default_token_uri() -> String {
+    GOOGLE_TOKEN_URI.to_string()
+}
+
+/// A token and when it expires
+struct CachedEntry {
+    token: Option<String>,
+    expires_at: Instant,
+}
+
+/// Tokens cached by scopes, shared by clones.
+///
+/// The lock is held while a token is refreshed, so concurrent callers wait for one refresh instead of each
+/// starting their own. Expiry follows tokio's clock, so it can be tested with paused time.
+#[derive(Clone, Default)]
+pub(crate) struct TokenCache {
+    entries: Arc<Mutex<HashMap<Vec<String>, CachedEntry>>>,
+}
+
+impl TokenCache {
+    /// Return the cached token for `scopes` unless it expires within `refresh_margin`, otherwise store and
+    /// return the token from `refresh` along with its expiry.
+    ///
+    /// If the refresh fails while the cached token has not expired yet, the cached token is returned instead.
+    pub(crate) async fn get_or_refresh<F>(
+        &self,
+        scopes: &[&str],
+        refresh_margin: Duration,
+        refresh: impl FnOnce() -> F,
+    ) -> GetTokenResult
+    where
+        F: Future<Output = Result<(Option<String>, Instant), GetTokenError>>,
+    {
+        let mut key: Vec<String> = scopes.iter().map(|scope| scope.to_string()).collect();
+        key.sort();
+        let mut entries = self.entries.lock().await;
+        let now = Instant::now();
+        if let Some(entry) = entries.get(&key)
+            && entry.expires_at > now + refresh_margin
+        {
+            return Ok(entry.token.clone());
+        }
+        match refresh().await {
+            Ok((token, expires_at)) => {
+                entries.insert(
+                    key,
+                    CachedEntry {
+                        token: token.clone(),
+                        expires_at,
+                    },
+                );
+                Ok(token)
+            }
+            Err(e) => match entries.get(&key) {
+                Some(entry) if entry.expires_at > Instant::now() => {
+                    log::warn!("Failed to refresh token, using cached token until it expires: {e}");
+                    Ok(entry.token.clone())
+                }
+                _ => Err(e),
+            },
+        }
+    }
+}
+
+/// [`GetToken`] that caches the tokens of another [`GetToken`].
+///
+/// [`GetToken`] doesn't report when a token expires, so every token is assumed to be valid for the lifetime
+/// passed to [`new`](Self::new), counted from when it was fetched. A token is refreshed once it is within the
+/// refresh margin of that expiry, five minutes by default. Concurrent calls share a single refresh, and clones
+/// share the cache.
+///
+/// The lifetime must not be longer than the tokens really last. Providers that cache tokens themselves can
+/// return a token that is already close to expiring, so the lifetime has to cover the shortest remaining
+/// validity they may hand out, not the lifetime of a fresh token. Otherwise FCM rejects requests with an expired
+/// token until the cached one is refreshed. The providers in this module already cache tokens with the expiry
+/// reported by Google and don't need wrapping.
+///
+/// ```no_run
+/// # fn example(auth: impl fcm_device_group::GetToken + Clone + 'static) {
+/// use std::time::Duration;
+///
+/// use fcm_device_group::{CachedToken, FCMDeviceGroupClient};
+///
+/// // `auth` fetches a new token on every call, each valid for an hour
+/// let auth = CachedToken::new(auth, Duration::from_secs(60 * 60));
+/// let client = FCMDeviceGroupClient::new("sender id", auth);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CachedToken<T> {
+    inner: T,
+    lifetime: Duration,
+    refresh_margin: Duration,
+    cache: TokenCache,
+}
+
+impl<T: GetToken> CachedToken<T> {
+    /// Cache the tokens returned by `inner`, treating each as valid for `lifetime` after it was fetched
+    pub fn new(inner: T, lifetime: Duration) -> Self {
+        Self {
+            inner,
+            lifetime,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cache: TokenCache::default(),
+        }
+    }
+
+    /// Set how long before it expires a token is refreshed.
+    ///
+    /// A margin as long as the lifetime refreshes the token on every call.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// The wrapped [`GetToken`]
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: GetToken + Clone + 'static> GetToken for CachedToken<T> {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> std::pin::Pin<Box<dyn Future<Output = GetTokenResult> + Send + 'a>> {
+        Box::pin(
+            self.cache
+                .get_or_refresh(scopes, self.refresh_margin, || async {
+                    let fetched_at = Instant::now();
+                    let token = self.inner.get_token(scopes).await?;
+                    Ok((token, fetched_at + self.lifetime))
+                }),
+        )
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt, path::Path, time::Duration};
+
+use reqwest::{Client as HttpClient, IntoUrl, Url};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use super::{DEFAULT_REFRESH_MARGIN, GetTokenResult, TokenCache, TokenResponse, default_token_uri};
+use crate::{GetToken, error::AuthorizedUserError};
+
+/// The fields of a gcloud user credentials file, as written by `gcloud auth application-default login`
+#[derive(Clone, Deserialize)]
+pub struct AuthorizedUserCredentials {
+    /// Id of the OAuth client the user signed in with
+    pub client_id: String,
+    /// Secret of the OAuth client the user signed in with
+    pub client_secret: String,
+    /// Refresh token of the user
+    pub refresh_token: String,
+    /// URI the refresh token is exchanged at
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+    /// Project used for quota and billing
+    #[serde(default)]
+    pub quota_project_id: Option<String>,
+}
+
+impl AuthorizedUserCredentials {
+    /// Parse user credentials from their JSON
+    pub fn from_json(json: &str) -> Result<Self, AuthorizedUserError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Read a user credentials file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AuthorizedUserError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl fmt::Debug for AuthorizedUserCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedUserCredentials")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"<redacted>")
+            .field("refresh_token", &"<redacted>")
+            .field("token_uri", &self.token_uri)
+            .field("quota_project_id", &self.quota_project_id)
+            .finish()
+    }
+}
+
+/// [`GetToken`] that gets access tokens with the refresh token of a user signed in with gcloud.
+///
+/// The scopes of the tokens are the ones chosen when signing in, so the `firebase.messaging` scope must be
+/// requested then with `gcloud auth application-default login --scopes`. Tokens are cached until shortly
+/// before they expire, and concurrent calls share a single refresh.
+#[derive(Clone)]
+pub struct AuthorizedUserAuth {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    quota_project_id: Option<String>,
+    token_uri: Url,
+    client: HttpClient,
+    refresh_margin: Duration,
+    cache: TokenCache,
+}
+
+impl AuthorizedUserAuth {
+    /// Authenticate with the given credentials
+    pub fn new(credentials: AuthorizedUserCredentials) -> Result<Self, AuthorizedUserError> {
+        Ok(Self {
+            token_uri: parse_token_uri(&credentials.token_uri)?,
+            client_id: credentials.client_id,
+            client_secret: credentials.client_secret,
+            refresh_token: credentials.refresh_token,
+            quota_project_id: credentials.quota_project_id,
+            client: HttpClient::new(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cache: TokenCache::default(),
+        })
+    }
+
+    /// Authenticate with credentials parsed from their JSON
+    pub fn from_json(json: &str) -> Result<Self, AuthorizedUserError> {
+        Self::new(AuthorizedUserCredentials::from_json(json)?)
+    }
+
+    /// Authenticate with the credentials in a user credentials file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, AuthorizedUserError> {
+        Self::new(AuthorizedUserCredentials::from_file(path)?)
+    }
+
+    /// Exchange the refresh token at the given URI instead of the one in the credentials
+    pub fn with_token_uri(mut self, token_uri: impl IntoUrl) -> Result<Self, AuthorizedUserError> {
+        self.token_uri = parse_token_uri(token_uri)?;
+        Ok(self)
+    }
+
+    /// Use the given http client to request tokens
+    pub fn with_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set how long before it expires a token is refreshed. Five minutes by default.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Project used for quota and billing, if the credentials name one
+    pub fn quota_project_id(&self) -> Option<&str> {
+        self.quota_project_id.as_deref()
+    }
+
+    /// URI the refresh token is exchanged at
+    pub fn token_uri(&self) -> &Url {
+        &self.token_uri
+    }
+
+    async fn fetch_token(&self) -> Result<(Option<String>, Instant), AuthorizedUserError> {
+        let requested_at = Instant::now();
+        let response = self
+            .client
+            .post(self.token_uri.clone())
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("refresh_token", &self.refresh_token),
+            ])
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            return Err(AuthorizedUserError::TokenResponse { status, body });
+        }
+        let token: TokenResponse = response.json().await?;
+        log::debug!("Got user access token expiring in {}s", token.expires_in);
+        Ok((
+            Some(token.access_token),
+            requested_at + Duration::from_secs(token.expires_in),
+        ))
+    }
+}
+
+impl fmt::Debug for AuthorizedUserAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizedUserAuth")
+            .field("client_id", &self.client_id)
+            .field("quota_project_id", &self.quota_project_id)
+            .field("token_uri", &self.token_uri)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GetToken for AuthorizedUserAuth {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> std::pin::Pin<Box<dyn Future<Output = GetTokenResult> + Send + 'a>> {
+        Box::pin(
+            self.cache
+                .get_or_refresh(scopes, self.refresh_margin, || async {
+                    Ok(self.fetch_token().await?)
+                }),
+        )
+    }
+}
+
+fn parse_token_uri(token_uri: impl IntoUrl) -> Result<Url, AuthorizedUserError> {
+    token_uri
+        .into_url()
+        .map_err(AuthorizedUserError::InvalidTokenUri)
+}
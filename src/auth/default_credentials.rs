@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    env, fmt,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+use reqwest::Client as HttpClient;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+#[cfg(feature = "service-account")]
+use super::ServiceAccountAuth;
+use super::{AuthorizedUserAuth, GetTokenResult, MetadataServerAuth};
+use crate::{
+    GetToken,
+    error::{CredentialsAttempt, DefaultCredentialsError, describe},
+};
+
+/// Environment variable naming a credentials file
+pub const CREDENTIALS_ENV: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+/// Environment variable providing the sender id, the number of the Firebase project
+pub const SENDER_ID_ENV: &str = "FCM_SENDER_ID";
+
+/// Environment variable overriding the gcloud configuration directory
+const GCLOUD_CONFIG_ENV: &str = "CLOUDSDK_CONFIG";
+
+const GCLOUD_CREDENTIALS_FILE: &str = "application_default_credentials.json";
+
+/// How long to wait for the metadata server before concluding there is none
+const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long to wait for a connection to the metadata server, which is local when there is one
+const METADATA_PROBE_CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Outcome of probing each metadata server host.
+///
+/// Whether a workload has a metadata server doesn't change while it runs, so each host is only probed once per
+/// process. The lock is held while probing, so concurrent lookups share a single probe.
+static METADATA_PROBES: LazyLock<Mutex<HashMap<String, Result<String, String>>>> =
+    LazyLock::new(Mutex::default);
+
+/// Where Application Default Credentials were looked for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialsSource {
+    /// The file named by [`CREDENTIALS_ENV`], if it is set
+    EnvironmentVariable(Option<PathBuf>),
+    /// The gcloud user credentials file, if its location is known
+    GcloudFile(Option<PathBuf>),
+    /// The metadata server at the given host
+    MetadataServer(String),
+}
+
+impl fmt::Display for CredentialsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EnvironmentVariable(Some(path)) => {
+                write!(f, "{CREDENTIALS_ENV} file {}", path.display())
+            }
+            Self::EnvironmentVariable(None) => f.write_str(CREDENTIALS_ENV),
+            Self::GcloudFile(Some(path)) => write!(f, "gcloud credentials file {}", path.display()),
+            Self::GcloudFile(None) => f.write_str("gcloud credentials file"),
+            Self::MetadataServer(host) => write!(f, "metadata server at {host}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Provider {
+    #[cfg(feature = "service-account")]
+    ServiceAccount(ServiceAccountAuth),
+    AuthorizedUser(AuthorizedUserAuth),
+    MetadataServer(MetadataServerAuth),
+}
+
+/// [`GetToken`] for the credentials found by following Google's Application Default Credentials order.
+///
+/// Credentials are looked for in
+///
+/// 1. the file named by [`CREDENTIALS_ENV`]
+/// 2. the gcloud user credentials file written by `gcloud auth application-default login`
+/// 3. the metadata server, see [`MetadataServerAuth`]
+///
+/// Credential files can hold gcloud user credentials, or a service account key with the `service-account`
+/// feature. If a file exists but can't be used, the later places are not tried. The metadata server is only
+/// probed once per process, waiting at most a few seconds.
+///
+/// The sender id is the project number, not the project id such as `my-project`. It is taken from
+/// [`SENDER_ID_ENV`] if it is set, otherwise from the metadata server. Credential files only name project ids,
+/// so with them the sender id has to be set explicitly.
+#[derive(Clone)]
+pub struct DefaultCredentials {
+    provider: Provider,
+    source: CredentialsSource,
+    sender_id: Option<String>,
+}
+
+impl DefaultCredentials {
+    /// Find the credentials to use
+    pub async fn find() -> Result<Self, DefaultCredentialsError> {
+        let sender_id = sender_id_from_env()?;
+        let mut attempts = Vec::new();
+
+        match env::var_os(CREDENTIALS_ENV).filter(|path| !path.is_empty()) {
+            Some(path) => {
+                let path = PathBuf::from(path);
+                let source = CredentialsSource::EnvironmentVariable(Some(path.clone()));
+                return Self::from_file(&path, source, sender_id, attempts);
+            }
+            None => attempts.push(CredentialsAttempt {
+                source: CredentialsSource::EnvironmentVariable(None),
+                reason: "not set".to_string(),
+            }),
+        }
+
+        match gcloud_credentials_path() {
+            Some(path) if path.is_file() => {
+                let source = CredentialsSource::GcloudFile(Some(path.clone()));
+                return Self::from_file(&path, source, sender_id, attempts);
+            }
+            path => attempts.push(CredentialsAttempt {
+                source: CredentialsSource::GcloudFile(path),
+                reason: "not found".to_string(),
+            }),
+        }
+
+        let metadata = MetadataServerAuth::new();
+        let source = CredentialsSource::MetadataServer(metadata.host().to_string());
+        match probe_metadata_server(&metadata).await {
+            Ok(project_number) => Ok(Self {
+                provider: Provider::MetadataServer(metadata),
+                source,
+                sender_id: sender_id.or(Some(project_number)),
+            }),
+            Err(reason) => {
+                attempts.push(CredentialsAttempt { source, reason });
+                Err(DefaultCredentialsError::NoCredentials(attempts))
+            }
+        }
+    }
+
+    /// Where the credentials were found
+    pub fn source(&self) -> &CredentialsSource {
+        &self.source
+    }
+
+    /// Sender id from [`SENDER_ID_ENV`] or the metadata server, if any
+    pub fn sender_id(&self) -> Option<&str> {
+        self.sender_id.as_deref()
+    }
+
+    fn from_file(
+        path: &Path,
+        source: CredentialsSource,
+        sender_id: Option<String>,
+        mut attempts: Vec<CredentialsAttempt>,
+    ) -> Result<Self, DefaultCredentialsError> {
+        match provider_from_file(path) {
+            Ok(provider) => Ok(Self {
+                provider,
+                source,
+                sender_id,
+            }),
+            Err(reason) => {
+                attempts.push(CredentialsAttempt { source, reason });
+                Err(DefaultCredentialsError::NoCredentials(attempts))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for DefaultCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DefaultCredentials")
+            .field("source", &self.source)
+            .field("sender_id", &self.sender_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GetToken for DefaultCredentials {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> std::pin::Pin<Box<dyn Future<Output = GetTokenResult> + Send + 'a>> {
+        match &self.provider {
+            #[cfg(feature = "service-account")]
+            Provider::ServiceAccount(auth) => auth.get_token(scopes),
+            Provider::AuthorizedUser(auth) => auth.get_token(scopes),
+            Provider::MetadataServer(auth) => auth.get_token(scopes),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CredentialsType {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// Read a credentials file, returning its provider or why it can't be used
+fn provider_from_file(path: &Path) -> Result<Provider, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| describe(&e))?;
+    let credentials: CredentialsType =
+        serde_json::from_str(&json).map_err(|e| format!("invalid credentials: {e}"))?;
+    match credentials.kind.as_str() {
+        "authorized_user" => {
+            let auth = AuthorizedUserAuth::from_json(&json).map_err(|e| describe(&e))?;
+            Ok(Provider::AuthorizedUser(auth))
+        }
+        #[cfg(feature = "service-account")]
+        "service_account" => {
+            let auth = ServiceAccountAuth::from_json(&json).map_err(|e| describe(&e))?;
+            Ok(Provider::ServiceAccount(auth))
+        }
+        #[cfg(not(feature = "service-account"))]
+        "service_account" => {
+            Err("service account keys need the service-account feature".to_string())
+        }
+        kind => Err(format!("unsupported credentials type {kind:?}")),
+    }
+}
+
+/// The sender id in [`SENDER_ID_ENV`], if it is set
+fn sender_id_from_env() -> Result<Option<String>, DefaultCredentialsError> {
+    match env::var(SENDER_ID_ENV) {
+        Ok(sender_id) if sender_id.is_empty() => Ok(None),
+        Ok(sender_id) if sender_id.bytes().all(|b| b.is_ascii_digit()) => Ok(Some(sender_id)),
+        Ok(sender_id) => Err(DefaultCredentialsError::InvalidSenderId(sender_id)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn gcloud_credentials_path() -> Option<PathBuf> {
+    let config_dir = match env::var_os(GCLOUD_CONFIG_ENV) {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(env::var_os("APPDATA")?).join("gcloud"),
+        None => PathBuf::from(env::var_os("HOME")?)
+            .join(".config")
+            .join("gcloud"),
+    };
+    Some(config_dir.join(GCLOUD_CREDENTIALS_FILE))
+}
+
+/// Check that a metadata server is reachable, returning its project number or why it isn't.
+///
+/// Results are cached per host in [`METADATA_PROBES`].
+async fn probe_metadata_server(metadata: &MetadataServerAuth) -> Result<String, String> {
+    let mut probes = METADATA_PROBES.lock().await;
+    if let Some(result) = probes.get(metadata.host()) {
+        log::debug!(
+            "Using cached probe of metadata server at {}",
+            metadata.host()
+        );
+        return result.clone();
+    }
+    let result = probe_uncached(metadata).await;
+    probes.insert(metadata.host().to_string(), result.clone());
+    result
+}
+
+async fn probe_uncached(metadata: &MetadataServerAuth) -> Result<String, String> {
+    let client = HttpClient::builder()
+        .timeout(METADATA_PROBE_TIMEOUT)
+        .connect_timeout(METADATA_PROBE_CONNECT_TIMEOUT)
+        .build()
+        .map_err(|e| describe(&e))?;
+    metadata
+        .clone()
+        .with_client(client)
+        .numeric_project_id()
+        .await
+        .map_err(|e| describe(&e))
+}
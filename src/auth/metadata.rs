@@ -0,0 +1,183 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use reqwest::{
+    Client as HttpClient, RequestBuilder, Response,
+    header::{HeaderName, HeaderValue},
+};
+use tokio::{sync::OnceCell, time::Instant};
+
+use super::{DEFAULT_REFRESH_MARGIN, GetTokenResult, TokenCache, TokenResponse};
+use crate::{GetToken, error::MetadataServerError};
+
+/// Host of the metadata server on GCE, GKE and Cloud Run
+pub const METADATA_HOST: &str = "metadata.google.internal";
+
+/// Environment variable overriding the metadata server host, as used by Google's client libraries
+pub const METADATA_HOST_ENV: &str = "GCE_METADATA_HOST";
+
+const METADATA_FLAVOR: HeaderName = HeaderName::from_static("metadata-flavor");
+const GOOGLE: HeaderValue = HeaderValue::from_static("Google");
+
+/// [`GetToken`] that gets access tokens from the GCE metadata server.
+///
+/// Works on GCE, on Cloud Run and on GKE with workload identity, using the service account attached to the
+/// workload. Tokens are cached until shortly before they expire, and concurrent calls share a single refresh.
+///
+/// The metadata server also knows which project the workload runs in. Its
+/// [`numeric_project_id`](Self::numeric_project_id) is the FCM sender id.
+///
+/// ```no_run
+/// use fcm_device_group::{FCMDeviceGroupClient, auth::MetadataServerAuth};
+///
+/// # async fn example() {
+/// let auth = MetadataServerAuth::new();
+/// let sender_id = auth.numeric_project_id().await.unwrap();
+/// let client = FCMDeviceGroupClient::new(&sender_id, auth).unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct MetadataServerAuth {
+    host: String,
+    service_account: String,
+    client: HttpClient,
+    refresh_margin: Duration,
+    cache: TokenCache,
+    project_id: Arc<OnceCell<String>>,
+    numeric_project_id: Arc<OnceCell<String>>,
+}
+
+impl MetadataServerAuth {
+    /// Use the default service account of the metadata server at [`METADATA_HOST`], or at the host in the
+    /// [`METADATA_HOST_ENV`] environment variable if it is set
+    pub fn new() -> Self {
+        Self {
+            host: std::env::var(METADATA_HOST_ENV).unwrap_or_else(|_| METADATA_HOST.to_string()),
+            service_account: "default".to_string(),
+            client: HttpClient::new(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cache: TokenCache::default(),
+            project_id: Arc::default(),
+            numeric_project_id: Arc::default(),
+        }
+    }
+
+    /// Use the metadata server at the given host, such as `127.0.0.1:8080` for a local fake
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Use the given service account attached to the workload instead of the default one
+    pub fn with_service_account(mut self, service_account: impl Into<String>) -> Self {
+        self.service_account = service_account.into();
+        self
+    }
+
+    /// Use the given http client to talk to the metadata server
+    pub fn with_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set how long before it expires a token is refreshed. Five minutes by default.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Host of the metadata server
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Id of the project the workload runs in, such as `my-project`
+    pub async fn project_id(&self) -> Result<String, MetadataServerError> {
+        self.project_id
+            .get_or_try_init(|| self.get_text("project/project-id"))
+            .await
+            .cloned()
+    }
+
+    /// Number of the project the workload runs in, which is the FCM sender id
+    pub async fn numeric_project_id(&self) -> Result<String, MetadataServerError> {
+        self.numeric_project_id
+            .get_or_try_init(|| self.get_text("project/numeric-project-id"))
+            .await
+            .cloned()
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.client
+            .get(format!("http://{}/computeMetadata/v1/{path}", self.host))
+            .header(METADATA_FLAVOR, GOOGLE)
+    }
+
+    async fn get_text(&self, path: &str) -> Result<String, MetadataServerError> {
+        let response = check_response(self.get(path).send().await?).await?;
+        Ok(response.text().await?.trim().to_string())
+    }
+
+    async fn fetch_token(
+        &self,
+        scopes: &[&str],
+    ) -> Result<(Option<String>, Instant), MetadataServerError> {
+        let requested_at = Instant::now();
+        let path = format!("instance/service-accounts/{}/token", self.service_account);
+        let mut request = self.get(&path);
+        if !scopes.is_empty() {
+            request = request.query(&[("scopes", scopes.join(","))]);
+        }
+        let response = check_response(request.send().await?).await?;
+        let token: TokenResponse = response.json().await?;
+        log::debug!(
+            "Got access token from the metadata server expiring in {}s",
+            token.expires_in
+        );
+        Ok((
+            Some(token.access_token),
+            requested_at + Duration::from_secs(token.expires_in),
+        ))
+    }
+}
+
+impl Default for MetadataServerAuth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for MetadataServerAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MetadataServerAuth")
+            .field("host", &self.host)
+            .field("service_account", &self.service_account)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GetToken for MetadataServerAuth {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> std::pin::Pin<Box<dyn Future<Output = GetTokenResult> + Send + 'a>> {
+        Box::pin(
+            self.cache
+                .get_or_refresh(scopes, self.refresh_margin, || async {
+                    Ok(self.fetch_token(scopes).await?)
+                }),
+        )
+    }
+}
+
+/// Check that the response is a success from a metadata server
+async fn check_response(response: Response) -> Result<Response, MetadataServerError> {
+    if response.headers().get(METADATA_FLAVOR) != Some(&GOOGLE) {
+        return Err(MetadataServerError::NotMetadataServer);
+    }
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await?;
+        return Err(MetadataServerError::ErrorResponse { status, body });
+    }
+    Ok(response)
+}
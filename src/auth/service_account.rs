@@ -0,0 +1,278 @@
+use std::{
+    fmt,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use reqwest::{Client as HttpClient, IntoUrl, Url};
+use ring::{
+    rand::SystemRandom,
+    signature::{RSA_PKCS1_SHA256, RsaKeyPair},
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::{DEFAULT_REFRESH_MARGIN, GetTokenResult, TokenCache, TokenResponse, default_token_uri};
+use crate::{GetToken, error::ServiceAccountError};
+
+const JWT_BEARER_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:jwt-bearer";
+
+/// Lifetime requested for the JWT assertion, the maximum Google accepts
+const ASSERTION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// The fields of a Google service account key file used to get access tokens
+#[derive(Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    /// Email address of the service account
+    pub client_email: String,
+    /// PEM encoded PKCS#8 RSA private key
+    pub private_key: String,
+    /// Id of the private key, sent as the `kid` of the JWT
+    #[serde(default)]
+    pub private_key_id: Option<String>,
+    /// URI the JWT assertion is exchanged at
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+    /// Project the service account belongs to
+    #[serde(default)]
+    pub project_id: Option<String>,
+}
+
+impl ServiceAccountKey {
+    /// Parse a service account key from its JSON
+    pub fn from_json(json: &str) -> Result<Self, ServiceAccountError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Read a service account key file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ServiceAccountError> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+impl fmt::Debug for ServiceAccountKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAccountKey")
+            .field("client_email", &self.client_email)
+            .field("private_key", &"<redacted>")
+            .field("private_key_id", &self.private_key_id)
+            .field("token_uri", &self.token_uri)
+            .field("project_id", &self.project_id)
+            .finish()
+    }
+}
+
+#[derive(Serialize)]
+struct JwtHeader<'a> {
+    alg: &'static str,
+    typ: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JwtClaims<'a> {
+    iss: &'a str,
+    scope: String,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+/// [`GetToken`] that gets access tokens with a Google service account key.
+///
+/// A JWT assertion signed with the key's private key is exchanged for an access token at the key's token URI.
+/// Tokens are cached until shortly before they expire, and concurrent calls share a single refresh.
+///
+/// ```no_run
+/// use fcm_device_group::{FCMDeviceGroupClient, auth::ServiceAccountAuth};
+///
+/// let auth = ServiceAccountAuth::from_file("service-account.json").unwrap();
+/// let client = FCMDeviceGroupClient::new("sender id", auth).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ServiceAccountAuth {
+    client_email: String,
+    private_key_id: Option<String>,
+    project_id: Option<String>,
+    key_pair: Arc<RsaKeyPair>,
+    token_uri: Url,
+    client: HttpClient,
+    refresh_margin: Duration,
+    cache: TokenCache,
+}
+
+impl ServiceAccountAuth {
+    /// Authenticate with the given key
+    pub fn new(key: ServiceAccountKey) -> Result<Self, ServiceAccountError> {
+        let key_pair = RsaKeyPair::from_pkcs8(&decode_pem(&key.private_key)?)
+            .map_err(|_| ServiceAccountError::InvalidPrivateKey)?;
+        Ok(Self {
+            client_email: key.client_email,
+            private_key_id: key.private_key_id,
+            project_id: key.project_id,
+            key_pair: Arc::new(key_pair),
+            token_uri: parse_token_uri(&key.token_uri)?,
+            client: HttpClient::new(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            cache: TokenCache::default(),
+        })
+    }
+
+    /// Authenticate with a key parsed from its JSON
+    pub fn from_json(json: &str) -> Result<Self, ServiceAccountError> {
+        Self::new(ServiceAccountKey::from_json(json)?)
+    }
+
+    /// Authenticate with the key in a service account key file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ServiceAccountError> {
+        Self::new(ServiceAccountKey::from_file(path)?)
+    }
+
+    /// Exchange the JWT assertion at the given URI instead of the one in the key
+    pub fn with_token_uri(mut self, token_uri: impl IntoUrl) -> Result<Self, ServiceAccountError> {
+        self.token_uri = parse_token_uri(token_uri)?;
+        Ok(self)
+    }
+
+    /// Use the given http client to request tokens
+    pub fn with_client(mut self, client: HttpClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set how long before it expires a token is refreshed. Five minutes by default.
+    pub fn with_refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Email address of the service account
+    pub fn client_email(&self) -> &str {
+        &self.client_email
+    }
+
+    /// Project the service account belongs to, if the key names one
+    pub fn project_id(&self) -> Option<&str> {
+        self.project_id.as_deref()
+    }
+
+    /// URI the JWT assertion is exchanged at
+    pub fn token_uri(&self) -> &Url {
+        &self.token_uri
+    }
+
+    /// Build and sign the JWT assertion for `scopes`
+    fn assertion(&self, scopes: &[&str]) -> Result<String, ServiceAccountError> {
+        let iat = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let header = JwtHeader {
+            alg: "RS256",
+            typ: "JWT",
+            kid: self.private_key_id.as_deref(),
+        };
+        let claims = JwtClaims {
+            iss: &self.client_email,
+            scope: scopes.join(" "),
+            aud: self.token_uri.as_str(),
+            iat,
+            exp: iat + ASSERTION_LIFETIME.as_secs(),
+        };
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).expect("JWT header serializes")),
+            URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).expect("JWT claims serialize")),
+        );
+        let mut signature = vec![0; self.key_pair.public().modulus_len()];
+        self.key_pair
+            .sign(
+                &RSA_PKCS1_SHA256,
+                &SystemRandom::new(),
+                message.as_bytes(),
+                &mut signature,
+            )
+            .map_err(|_| ServiceAccountError::Signing)?;
+        Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+    }
+
+    async fn fetch_token(
+        &self,
+        scopes: &[&str],
+    ) -> Result<(Option<String>, Instant), ServiceAccountError> {
+        let requested_at = Instant::now();
+        let assertion = self.assertion(scopes)?;
+        let response = self
+            .client
+            .post(self.token_uri.clone())
+            .form(&[
+                ("grant_type", JWT_BEARER_GRANT_TYPE),
+                ("assertion", &assertion),
+            ])
+            .send()
+            .await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await?;
+            return Err(ServiceAccountError::TokenResponse { status, body });
+        }
+        let token: TokenResponse = response.json().await?;
+        log::debug!(
+            "Got access token for {} expiring in {}s",
+            self.client_email,
+            token.expires_in
+        );
+        Ok((
+            Some(token.access_token),
+            requested_at + Duration::from_secs(token.expires_in),
+        ))
+    }
+}
+
+impl fmt::Debug for ServiceAccountAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServiceAccountAuth")
+            .field("client_email", &self.client_email)
+            .field("project_id", &self.project_id)
+            .field("token_uri", &self.token_uri)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GetToken for ServiceAccountAuth {
+    fn get_token<'a>(
+        &'a self,
+        scopes: &'a [&str],
+    ) -> std::pin::Pin<Box<dyn Future<Output = GetTokenResult> + Send + 'a>> {
+        Box::pin(
+            self.cache
+                .get_or_refresh(scopes, self.refresh_margin, || async {
+                    Ok(self.fetch_token(scopes).await?)
+                }),
+        )
+    }
+}
+
+fn parse_token_uri(token_uri: impl IntoUrl) -> Result<Url, ServiceAccountError> {
+    token_uri
+        .into_url()
+        .map_err(ServiceAccountError::InvalidTokenUri)
+}
+
+/// Decode the DER inside a PEM block
+fn decode_pem(pem: &str) -> Result<Vec<u8>, ServiceAccountError> {
+    let base64: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    STANDARD
+        .decode(base64)
+        .map_err(|_| ServiceAccountError::InvalidPrivateKey)
+}
@@ -0,0 +1,61 @@
+//! Bookkeeping for calls whose registration ids are split into several requests, shared by the async and blocking
+//! clients
+use std::collections::VecDeque;
+
+use crate::{
+    FCMDeviceGroup, RegistrationToken,
+    error::{FCMDeviceGroupsRequestError, PartialBatchFailure, operation_errors},
+};
+
+/// Registration ids of a call split into batches FCM accepts, and which of them were applied
+pub(crate) struct Batches {
+    applied: Vec<Vec<RegistrationToken>>,
+    remaining: VecDeque<Vec<RegistrationToken>>,
+}
+
+impl Batches {
+    /// Split registration ids into batches of at most `max_registration_ids`. There is always at least one batch.
+    pub(crate) fn new(
+        registration_ids: Vec<RegistrationToken>,
+        max_registration_ids: usize,
+    ) -> Self {
+        let remaining = if registration_ids.len() <= max_registration_ids {
+            VecDeque::from([registration_ids])
+        } else {
+            registration_ids
+                .chunks(max_registration_ids)
+                .map(<[RegistrationToken]>::to_vec)
+                .collect()
+        };
+        Self {
+            applied: Vec::new(),
+            remaining,
+        }
+    }
+
+    /// Take the next batch to apply
+    pub(crate) fn next_batch(&mut self) -> Option<Vec<RegistrationToken>> {
+        self.remaining.pop_front()
+    }
+
+    /// Note that a batch taken with [`next_batch`](Self::next_batch) was applied
+    pub(crate) fn applied(&mut self, batch: Vec<RegistrationToken>) {
+        self.applied.push(batch);
+    }
+
+    /// The error for `batch` failing, when the batches applied before it left the group as `group`
+    pub(crate) fn failed(
+        self,
+        group: FCMDeviceGroup,
+        batch: Vec<RegistrationToken>,
+        source: FCMDeviceGroupsRequestError<operation_errors::ChangeGroupMembersError>,
+    ) -> Box<PartialBatchFailure> {
+        Box::new(PartialBatchFailure {
+            group,
+            applied: self.applied,
+            failed: batch,
+            remaining: self.remaining.into(),
+            source,
+        })
+    }
+}
@@ -0,0 +1,320 @@
+//! Blocking client for synchronous code.
+//!
+//! Mirrors the device group operations of the async [`FCMDeviceGroupClient`](crate::FCMDeviceGroupClient) on top
+//! of reqwest's blocking client, with the same [`Operation`] and error types. Retries, batching, registries and
+//! the http settings of [`FCMDeviceGroupClientBuilder`] work the same way, see
+//! [`FCMDeviceGroupClientBuilder::build_blocking`]. Sending messages, sharding, reconciling and topics are only
+//! available on the async client.
+//!
+//! reqwest's blocking client times out after 30 seconds by default, while the async client has no timeout.
+//!
+//! [`GetToken`] is async, so each client drives it on a private single threaded tokio runtime. Like
+//! [`reqwest::blocking`], the client must not be created, used or dropped from within an async runtime.
+use std::sync::Arc;
+
+use reqwest::{
+    IntoUrl, Url,
+    blocking::{Client as HttpClient, RequestBuilder, Response},
+    header::{self, HeaderValue},
+};
+use serde::de::DeserializeOwned;
+use tokio::runtime::{Builder as RuntimeBuilder, Runtime};
+
+use crate::{
+    ChangeMembers, FCM_DEVICE_GROUP_SCOPES, FCMDeviceGroup, FCMDeviceGroupClientBuilder,
+    FIREBASE_NOTIFICATION_URL, GetToken, GroupRegistry, NotificationKeyName, Operation,
+    OperationResponse, RegistrationToken, RetryPolicy,
+    batch::Batches,
+    error::{
+        self, FCMDeviceGroupClientCreationError, FCMDeviceGroupError, FCMDeviceGroupsRequestError,
+        RawError, operation_errors::OperationResult,
+    },
+    observe::Observation,
+    registry,
+    retry::Decision,
+};
+
+/// Blocking client to use fcm device groups
+#[derive(Clone)]
+pub struct FCMDeviceGroupClient {
+    url: Url,
+    client: HttpClient,
+    auth: Box<dyn GetToken + 'static>,
+    runtime: Arc<Runtime>,
+    retry_policy: RetryPolicy,
+    max_registration_ids: usize,
+    registry: Option<Arc<dyn GroupRegistry>>,
+}
+
+impl FCMDeviceGroupClient {
+    /// Creates a new `FCMDeviceGroupClient` with the default url and the provided bearer auth string
+    pub fn new(
+        sender_id: &str,
+        auth: impl GetToken + 'static,
+    ) -> Result<Self, FCMDeviceGroupClientCreationError> {
+        Self::with_url(FIREBASE_NOTIFICATION_URL, sender_id, auth)
+    }
+
+    /// Creates a new `FCMDeviceGroupClient` with the given url and the provided bearer auth string
+    pub fn with_url(
+        url: impl IntoUrl,
+        sender_id: &str,
+        auth: impl GetToken + 'static,
+    ) -> Result<Self, FCMDeviceGroupClientCreationError> {
+        Self::builder(sender_id, auth).url(url).build_blocking()
+    }
+
+    /// Start building a client for the given sender id and auth, finished with
+    /// [`build_blocking`](FCMDeviceGroupClientBuilder::build_blocking)
+    pub fn builder(sender_id: &str, auth: impl GetToken + 'static) -> FCMDeviceGroupClientBuilder {
+        crate::FCMDeviceGroupClient::builder(sender_id, auth)
+    }
+
+    pub(crate) fn from_parts(
+        url: Url,
+        client: HttpClient,
+        auth: Box<dyn GetToken + 'static>,
+        retry_policy: RetryPolicy,
+        max_registration_ids: usize,
+        registry: Option<Arc<dyn GroupRegistry>>,
+    ) -> Result<Self, FCMDeviceGroupClientCreationError> {
+        let runtime = RuntimeBuilder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(FCMDeviceGroupClientCreationError::Runtime)?;
+        Ok(Self {
+            url,
+            client,
+            auth,
+            runtime: Arc::new(runtime),
+            retry_policy,
+            max_registration_ids,
+            registry,
+        })
+    }
+
+    /// Set the policy used to retry failed requests. By default requests are not retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the maximum number of registration ids sent in a single request.
+    /// See [`crate::FCMDeviceGroupClient::with_max_registration_ids_per_request`].
+    pub fn with_max_registration_ids_per_request(mut self, max_registration_ids: usize) -> Self {
+        self.max_registration_ids = max_registration_ids.max(1);
+        self
+    }
+
+    /// Record every successful change to a group's membership in the given registry
+    pub fn with_registry(mut self, registry: Arc<dyn GroupRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// The registry changes are recorded in, if one was set
+    pub fn registry(&self) -> Option<&dyn GroupRegistry> {
+        self.registry.as_deref()
+    }
+
+    /// Apply the given operation with with the client.
+    pub fn apply(
+        &self,
+        operation: Operation,
+    ) -> Result<
+        OperationResponse,
+        error::FCMDeviceGroupsRequestError<error::FCMDeviceGroupsBadRequest>,
+    > {
+        let observation = Observation::start(
+            operation.kind(),
+            operation.notification_key_name(),
+            Some(operation.registration_ids().len()),
+        );
+        observation.run_blocking(|| {
+            let response = self.execute(
+                || self.client.post(self.url.clone()).json(&operation),
+                operation.is_idempotent(),
+            );
+            registry::record_operation(self.registry(), &operation, &response);
+            response
+        })
+    }
+
+    /// Create a new group with the provided name and ID
+    ///
+    /// If there are more registration ids than fit in one request, the group is created with the first batch and
+    /// the rest are added afterwards.
+    pub fn create_group(
+        &self,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::CreateGroupError> {
+        let observation = Observation::start(
+            "create",
+            Some(&notification_key_name),
+            Some(registration_ids.len()),
+        );
+        observation.run_blocking(|| {
+            let mut batches = Batches::new(registration_ids, self.max_registration_ids);
+            let first = batches.next_batch().unwrap_or_default();
+            let group = self.apply_operation(Operation::Create {
+                notification_key_name,
+                registration_ids: first.clone(),
+            })?;
+            batches.applied(first);
+            self.apply_batches(group, batches, ChangeMembers::Add)
+                .map_err(FCMDeviceGroupsRequestError::PartialBatchFailure)
+        })
+    }
+
+    /// Add a set of registration IDS to the group
+    pub fn add_to_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
+        self.change_members(group, registration_ids, ChangeMembers::Add)
+    }
+
+    /// Remove a set of registration IDS to the group
+    pub fn remove_from_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
+        self.change_members(group, registration_ids, ChangeMembers::Remove)
+    }
+
+    /// Use this client to request the notification key for a given name
+    pub fn get_key(
+        &self,
+        notification_key_name: NotificationKeyName,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::GetKeyError> {
+        let observation = Observation::start("get_key", Some(&notification_key_name), None);
+        observation.run_blocking(|| {
+            let request = || {
+                self.client
+                    .get(self.url.clone())
+                    .query(&[("notification_key_name", notification_key_name.as_str())])
+                    .header(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static("application/json"),
+                    )
+            };
+            let response: OperationResponse = self.execute(request, true)?;
+            Ok(FCMDeviceGroup {
+                notification_key_name,
+                notification_key: response.notification_key,
+            })
+        })
+    }
+
+    fn send_raw(&self, request: RequestBuilder) -> Result<Response, RawError> {
+        let token = self
+            .runtime
+            .block_on(self.auth.get_token(FCM_DEVICE_GROUP_SCOPES))
+            .map_err(RawError::GetTokenError)?;
+        let request = match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        Ok(request.send()?)
+    }
+
+    /// Send the request built by `request`, retrying according to the client's [`RetryPolicy`].
+    fn execute<T: DeserializeOwned, E: FCMDeviceGroupError>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        idempotent: bool,
+    ) -> OperationResult<T, E> {
+        let max_attempts = self.retry_policy.max_attempts();
+        let mut attempt = 1;
+        loop {
+            log::debug!("Sending FCM request, attempt {attempt}/{max_attempts}");
+            let result = self.send_raw(request());
+            let outcome = result
+                .as_ref()
+                .map(|response| (response.status(), response.headers()));
+            let decision = self.retry_policy.decide(outcome, attempt, idempotent);
+            if let Decision::Retry(delay) = decision {
+                std::thread::sleep(delay);
+                attempt += 1;
+                continue;
+            }
+            let result = match result {
+                Ok(response) => {
+                    let status = response.status();
+                    match response.bytes() {
+                        Ok(body) => FCMDeviceGroupsRequestError::from_response(status, &body),
+                        Err(e) => Err(e.into()),
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            return decision.finish(result, attempt);
+        }
+    }
+
+    fn change_members(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+        change: ChangeMembers,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
+        let observation = Observation::start(
+            change.kind(),
+            Some(&group.notification_key_name),
+            Some(registration_ids.len()),
+        );
+        observation.run_blocking(|| {
+            let mut batches = Batches::new(registration_ids, self.max_registration_ids);
+            let first = batches.next_batch().unwrap_or_default();
+            let group = self.apply_operation(change.operation(group, first.clone()))?;
+            batches.applied(first);
+            self.apply_batches(group, batches, change)
+                .map_err(FCMDeviceGroupsRequestError::PartialBatchFailure)
+        })
+    }
+
+    /// Apply the batches left after the first one was applied to `group`
+    fn apply_batches(
+        &self,
+        mut group: FCMDeviceGroup,
+        mut batches: Batches,
+        change: ChangeMembers,
+    ) -> Result<FCMDeviceGroup, Box<error::PartialBatchFailure>> {
+        while let Some(batch) = batches.next_batch() {
+            let operation = change.operation(group.clone(), batch.clone());
+            match self.apply_operation(operation) {
+                Ok(updated) => {
+                    group = updated;
+                    batches.applied(batch);
+                }
+                Err(source) => return Err(batches.failed(group, batch, source)),
+            }
+        }
+        Ok(group)
+    }
+
+    fn apply_operation<E: FCMDeviceGroupError + 'static>(
+        &self,
+        operation: Operation,
+    ) -> OperationResult<FCMDeviceGroup, E> {
+        let notification_key_name = operation
+            .notification_key_name()
+            .cloned()
+            .ok_or(FCMDeviceGroupsRequestError::MissingKeyName)?;
+        let response = self.execute(
+            || self.client.post(self.url.clone()).json(&operation),
+            operation.is_idempotent(),
+        );
+        registry::record_operation(self.registry(), &operation, &response);
+        let response: OperationResponse = response?;
+        let group = FCMDeviceGroup {
+            notification_key_name,
+            notification_key: response.notification_key,
+        };
+        registry::record_change(self.registry(), &group, &operation);
+        Ok(group)
+    }
+}
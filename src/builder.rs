@@ -0,0 +1,227 @@
+use std::{sync::Arc, time::Duration};
+
+#[cfg(feature = "default-tls")]
+use reqwest::Certificate;
+use reqwest::{
+    Client as HttpClient, IntoUrl, Proxy, Url,
+    header::{self, HeaderMap},
+};
+
+use crate::{
+    FCMDeviceGroupClient, FIREBASE_NOTIFICATION_URL, GetToken, GroupRegistry,
+    MAX_REGISTRATION_IDS_PER_REQUEST, RetryPolicy,
+    auth::DefaultCredentials,
+    default_send_url,
+    error::{DefaultCredentialsError, FCMDeviceGroupClientCreationError},
+};
+
+/// Apply the http settings of a [`FCMDeviceGroupClientBuilder`] to an async or blocking reqwest client builder
+macro_rules! configure_http_client {
+    ($builder:ident, $client:expr) => {{
+        let mut client = $client
+            .default_headers(default_headers(&$builder.sender_id)?)
+            .connection_verbose($builder.connection_verbose);
+        if let Some(timeout) = $builder.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(connect_timeout) = $builder.connect_timeout {
+            client = client.connect_timeout(connect_timeout);
+        }
+        if let Some(proxy) = $builder.proxy {
+            client = client.proxy(proxy);
+        }
+        if let Some(user_agent) = $builder.user_agent {
+            client = client.user_agent(user_agent);
+        }
+        #[cfg(feature = "default-tls")]
+        if !$builder.root_certificates.is_empty() {
+            client = client.tls_certs_merge($builder.root_certificates);
+        }
+        client
+    }};
+}
+
+/// Builder for [`FCMDeviceGroupClient`].
+///
+/// The `project_id` and `access_token_auth` headers FCM requires are always added to the built client. With the
+/// `blocking` feature, [`build_blocking`](Self::build_blocking) builds a blocking client instead.
+pub struct FCMDeviceGroupClientBuilder {
+    sender_id: String,
+    auth: Box<dyn GetToken + 'static>,
+    url: Result<Url, reqwest::Error>,
+    send_url: Option<Url>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    user_agent: Option<String>,
+    connection_verbose: bool,
+    #[cfg(feature = "default-tls")]
+    root_certificates: Vec<Certificate>,
+    retry_policy: RetryPolicy,
+    max_registration_ids: usize,
+    registry: Option<Arc<dyn GroupRegistry>>,
+}
+
+impl FCMDeviceGroupClient {
+    /// Start building a client for the given sender id and auth
+    pub fn builder(sender_id: &str, auth: impl GetToken + 'static) -> FCMDeviceGroupClientBuilder {
+        FCMDeviceGroupClientBuilder {
+            sender_id: sender_id.to_owned(),
+            auth: Box::new(auth),
+            url: Ok(Url::parse(FIREBASE_NOTIFICATION_URL).expect("The default url is valid")),
+            send_url: None,
+            timeout: None,
+            connect_timeout: None,
+            proxy: None,
+            user_agent: None,
+            connection_verbose: false,
+            #[cfg(feature = "default-tls")]
+            root_certificates: Vec::new(),
+            retry_policy: RetryPolicy::disabled(),
+            max_registration_ids: MAX_REGISTRATION_IDS_PER_REQUEST,
+            registry: None,
+        }
+    }
+
+    /// Start building a client with [`DefaultCredentials`] and the sender id found with them.
+    ///
+    /// Fails with [`DefaultCredentialsError::MissingSenderId`] if there is no sender id, use
+    /// [`builder`](Self::builder) with [`DefaultCredentials::find`] to provide one another way.
+    pub async fn builder_from_env() -> Result<FCMDeviceGroupClientBuilder, DefaultCredentialsError>
+    {
+        let credentials = DefaultCredentials::find().await?;
+        let sender_id = credentials
+            .sender_id()
+            .ok_or_else(|| DefaultCredentialsError::MissingSenderId(credentials.source().clone()))?
+            .to_owned();
+        Ok(Self::builder(&sender_id, credentials))
+    }
+}
+
+impl FCMDeviceGroupClientBuilder {
+    /// Set the device group url. Defaults to [`FIREBASE_NOTIFICATION_URL`].
+    pub fn url(mut self, url: impl IntoUrl) -> Self {
+        self.url = url.into_url();
+        self
+    }
+
+    /// Set the url messages are sent to. Defaults to `projects/{sender_id}/messages:send` under
+    /// [`FIREBASE_SEND_URL`](crate::FIREBASE_SEND_URL).
+    pub fn send_url(mut self, send_url: Url) -> Self {
+        self.send_url = Some(send_url);
+        self
+    }
+
+    /// Set a timeout for each request, from connecting until the response body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a timeout for connecting to FCM
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Send all requests through the given proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Set the `User-Agent` header sent with every request
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Log connection reads and writes at the trace level. Disabled by default.
+    ///
+    /// The raw requests include the bearer token, registration ids and notification keys, so only enable this
+    /// while debugging.
+    pub fn connection_verbose(mut self, connection_verbose: bool) -> Self {
+        self.connection_verbose = connection_verbose;
+        self
+    }
+
+    /// Trust an additional root certificate, on top of the built in roots.
+    ///
+    /// Only available with the `default-tls` feature, the TLS backend this builder configures. With another
+    /// reqwest TLS backend, set the certificates on your own reqwest client and pass it to
+    /// [`FCMDeviceGroupClient::with_client`], which leaves adding the required headers to you.
+    #[cfg(feature = "default-tls")]
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Set the policy used to retry failed requests. By default requests are not retried.
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Set the maximum number of registration ids sent in a single request.
+    /// See [`FCMDeviceGroupClient::with_max_registration_ids_per_request`].
+    pub fn max_registration_ids_per_request(mut self, max_registration_ids: usize) -> Self {
+        self.max_registration_ids = max_registration_ids.max(1);
+        self
+    }
+
+    /// Record every successful change to a group's membership in the given registry
+    pub fn registry(mut self, registry: Arc<dyn GroupRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// Build the client
+    pub fn build(self) -> Result<FCMDeviceGroupClient, FCMDeviceGroupClientCreationError> {
+        let client = configure_http_client!(self, HttpClient::builder()).build()?;
+        Ok(FCMDeviceGroupClient {
+            url: self
+                .url
+                .map_err(FCMDeviceGroupClientCreationError::InvalidUrl)?,
+            send_url: Some(
+                self.send_url
+                    .unwrap_or_else(|| default_send_url(&self.sender_id)),
+            ),
+            client,
+            auth: self.auth,
+            retry_policy: self.retry_policy,
+            max_registration_ids: self.max_registration_ids,
+            registry: self.registry,
+        })
+    }
+
+    /// Build a [`blocking::FCMDeviceGroupClient`](crate::blocking::FCMDeviceGroupClient) with the same settings.
+    ///
+    /// The send url is ignored since the blocking client doesn't send messages. Unlike the async client,
+    /// reqwest's blocking client times out after 30 seconds unless [`timeout`](Self::timeout) is set.
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(
+        self,
+    ) -> Result<crate::blocking::FCMDeviceGroupClient, FCMDeviceGroupClientCreationError> {
+        let client = configure_http_client!(self, reqwest::blocking::Client::builder()).build()?;
+        crate::blocking::FCMDeviceGroupClient::from_parts(
+            self.url
+                .map_err(FCMDeviceGroupClientCreationError::InvalidUrl)?,
+            client,
+            self.auth,
+            self.retry_policy,
+            self.max_registration_ids,
+            self.registry,
+        )
+    }
+}
+
+/// Headers FCM requires on every device group request
+pub(crate) fn default_headers(sender_id: &str) -> Result<HeaderMap, header::InvalidHeaderValue> {
+    let mut headers = HeaderMap::new();
+    headers.insert("project_id", header::HeaderValue::try_from(sender_id)?);
+    headers.insert(
+        "access_token_auth",
+        header::HeaderValue::from_static("true"),
+    );
+    Ok(headers)
+}
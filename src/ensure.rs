@@ -0,0 +1,84 @@
+use crate::{
+    FCMDeviceGroup, FCMDeviceGroupClient, NotificationKeyName, RegistrationToken,
+    error::{
+        EnsureGroupError, FCMDeviceGroupsRequestError,
+        operation_errors::{ChangeGroupMembersError, CreateGroupError, GetKeyError},
+    },
+};
+
+/// How many times [`FCMDeviceGroupClient::ensure_group`] tries to create or find the group before giving up
+const MAX_ENSURE_ATTEMPTS: u32 = 3;
+
+/// Group returned by [`FCMDeviceGroupClient::ensure_group`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnsuredGroup {
+    /// The device group
+    pub group: FCMDeviceGroup,
+    /// Whether the group was created by this call, rather than already existing
+    pub created: bool,
+}
+
+impl FCMDeviceGroupClient {
+    /// Create the group, or add the registration ids to it if a group with this name already exists.
+    ///
+    /// If the group is deleted by someone else between finding out it exists and adding to it, creating it is
+    /// tried again. FCM can't create a group without members, so with no registration ids the existing group is
+    /// only looked up.
+    pub async fn ensure_group(
+        &self,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> Result<EnsuredGroup, EnsureGroupError> {
+        if registration_ids.is_empty() {
+            let group = self
+                .get_key(notification_key_name)
+                .await
+                .map_err(EnsureGroupError::GetKey)?;
+            return Ok(EnsuredGroup {
+                group,
+                created: false,
+            });
+        }
+
+        for _ in 0..MAX_ENSURE_ATTEMPTS {
+            match self
+                .create_group(notification_key_name.clone(), registration_ids.clone())
+                .await
+            {
+                Ok(group) => {
+                    return Ok(EnsuredGroup {
+                        group,
+                        created: true,
+                    });
+                }
+                Err(FCMDeviceGroupsRequestError::BadRequestError(
+                    CreateGroupError::AlreadyExists,
+                )) => {}
+                Err(e) => return Err(EnsureGroupError::Create(e)),
+            }
+
+            let group = match self.get_key(notification_key_name.clone()).await {
+                Ok(group) => group,
+                Err(FCMDeviceGroupsRequestError::BadRequestError(GetKeyError::KeyNotFound)) => {
+                    continue;
+                }
+                Err(e) => return Err(EnsureGroupError::GetKey(e)),
+            };
+            match self.add_to_group(group, registration_ids.clone()).await {
+                Ok(group) => {
+                    return Ok(EnsuredGroup {
+                        group,
+                        created: false,
+                    });
+                }
+                Err(FCMDeviceGroupsRequestError::BadRequestError(
+                    ChangeGroupMembersError::KeyNotFound,
+                )) => {}
+                Err(e) => return Err(EnsureGroupError::ChangeMembers(e)),
+            }
+        }
+        Err(EnsureGroupError::Contended {
+            attempts: MAX_ENSURE_ATTEMPTS,
+        })
+    }
+}
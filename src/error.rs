@@ -6,6 +6,8 @@ use reqwest::{StatusCode, header::InvalidHeaderValue};
 use serde::{Deserialize, de::DeserializeOwned};
 use thiserror::Error;
 
+use crate::{FCMDeviceGroup, RegistrationToken, auth::CredentialsSource, topic::TopicResult};
+
 /// Error when creating FCM Device Groups Client
 #[derive(Debug, Error)]
 pub enum FCMDeviceGroupClientCreationError {
@@ -15,11 +17,23 @@ pub enum FCMDeviceGroupClientCreationError {
     #[allow(missing_docs)]
     #[error("Build Client Error")]
     ClientBuild(#[from] reqwest::Error),
+    /// The FCM url could not be parsed
+    #[error("Invalid FCM URL")]
+    InvalidUrl(#[source] reqwest::Error),
+    /// The runtime used to get auth tokens could not be started
+    #[cfg(feature = "blocking")]
+    #[error("Error Starting Runtime")]
+    Runtime(#[source] std::io::Error),
 }
 
 #[allow(missing_docs)]
 pub trait FCMDeviceGroupError: std::error::Error + Sized {
     fn from_error_str(error: FCMDeviceGroupsBadRequest) -> Option<Self>;
+
+    /// Short snake case name of the error, used to label traces and metrics
+    fn kind(&self) -> &'static str {
+        "bad_request"
+    }
 }
 
 /// Error When Making an FCM Device Groups Request
@@ -36,28 +50,389 @@ pub enum FCMDeviceGroupsRequestError<E: FCMDeviceGroupError> {
     /// Parsed Bad Request Error
     #[error("Bad Request")]
     BadRequestError(#[from] E),
+    /// FCM returned a non success response that is not a request specific error
+    #[error("Error Response From FCM: {0}")]
+    ErrorResponse(FCMErrorResponse),
+    /// The operation has no notification key name, which is required to identify the resulting group
+    #[error("Operation Is Missing A Notification Key Name")]
+    MissingKeyName,
+    /// A registration token passed as a string failed validation
+    #[error("Invalid Registration Token")]
+    InvalidRegistrationToken(#[source] InvalidIdentifierError),
+    /// FCM returned a success response whose body could not be parsed
+    #[error("Invalid Response From FCM")]
+    InvalidResponse(#[source] serde_json::Error),
+    /// The client has no url to send messages to, see
+    /// [`FCMDeviceGroupClient::with_send_url`](crate::FCMDeviceGroupClient::with_send_url)
+    #[error("No Url To Send Messages To")]
+    MissingSendUrl,
+    /// A later batch of registration ids failed after earlier batches of the same call were applied
+    #[error("Batch Of Registration IDs Failed")]
+    PartialBatchFailure(#[source] Box<PartialBatchFailure>),
+    /// The request kept failing with retryable errors until the retry policy gave up
+    #[error("Request Failed After {attempts} Attempts")]
+    RetriesExhausted {
+        /// Number of attempts made
+        attempts: u32,
+        /// Error from the last attempt
+        source: Box<Self>,
+    },
 }
 
 impl<E: FCMDeviceGroupError> FCMDeviceGroupsRequestError<E> {
-    pub(crate) async fn json_response<T: DeserializeOwned>(
-        resp: reqwest::Response,
+    pub(crate) fn with_attempts(self, attempts: u32) -> Self {
+        if attempts > 1 {
+            Self::RetriesExhausted {
+                attempts,
+                source: Box::new(self),
+            }
+        } else {
+            self
+        }
+    }
+
+    /// Short snake case name of the error, used to label traces and metrics
+    #[cfg_attr(not(any(feature = "tracing", feature = "metrics")), allow(dead_code))]
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::HttpError(e) if e.is_timeout() => "timeout",
+            Self::HttpError(e) if e.is_connect() => "connect",
+            Self::HttpError(_) => "http",
+            Self::GetTokenError(_) => "get_token",
+            Self::BadRequestError(e) => e.kind(),
+            Self::ErrorResponse(response) if response.status.is_server_error() => "server_error",
+            Self::ErrorResponse(_) => "client_error",
+            Self::MissingKeyName => "missing_key_name",
+            Self::InvalidRegistrationToken(_) => "invalid_registration_token",
+            Self::InvalidResponse(_) => "invalid_response",
+            Self::MissingSendUrl => "missing_send_url",
+            Self::PartialBatchFailure(failure) => failure.source.kind(),
+            Self::RetriesExhausted { source, .. } => source.kind(),
+        }
+    }
+
+    /// Parse the body of a response FCM returned with the given status, shared by the async and blocking clients
+    pub(crate) fn from_response<T: DeserializeOwned>(
+        status: StatusCode,
+        body: &[u8],
     ) -> Result<T, Self> {
-        match resp.error_for_status_ref() {
-            Ok(_) => Ok(resp.json::<T>().await?),
-            Err(e) => match e.status().unwrap() {
-                StatusCode::BAD_REQUEST => {
-                    let string_error = resp.json::<FCMDeviceGroupsBadRequest>().await?;
-                    Err(match E::from_error_str(string_error) {
-                        Some(custom_error) => Self::BadRequestError(custom_error),
-                        None => Self::HttpError(e),
-                    })
-                }
-                _ => Err(Self::HttpError(e)),
-            },
+        if status.is_client_error() || status.is_server_error() {
+            let body = String::from_utf8_lossy(body).into_owned();
+            return Err(Self::from_error_body(status, body));
+        }
+        serde_json::from_slice(body).map_err(Self::InvalidResponse)
+    }
+
+    fn from_error_body(status: StatusCode, body: String) -> Self {
+        let error = parse_error_message(&body);
+        if status == StatusCode::BAD_REQUEST
+            && let Some(custom_error) = error
+                .clone()
+                .and_then(|error| E::from_error_str(FCMDeviceGroupsBadRequest { error }))
+        {
+            return Self::BadRequestError(custom_error);
+        }
+        Self::ErrorResponse(FCMErrorResponse {
+            status,
+            body,
+            error,
+        })
+    }
+}
+
+/// Error reconciling a group with [`FCMDeviceGroupClient::reconcile`](crate::FCMDeviceGroupClient::reconcile)
+#[derive(Debug, Error)]
+pub enum ReconcileError {
+    /// Looking up the group failed
+    #[error("Error Looking Up Group")]
+    GetKey(#[source] FCMDeviceGroupsRequestError<operation_errors::GetKeyError>),
+    /// Creating the missing group, or adding to it if it was created concurrently, failed
+    #[error("Error Creating Group")]
+    Ensure(#[from] EnsureGroupError),
+    /// Adding or removing members failed
+    #[error("Error Changing Group Members")]
+    ChangeMembers(#[source] FCMDeviceGroupsRequestError<operation_errors::ChangeGroupMembersError>),
+    /// Reading the known members from the registry failed
+    #[error("Error Reading Registry")]
+    Registry(#[from] crate::registry::RegistryError),
+}
+
+/// Error ensuring a group exists with [`FCMDeviceGroupClient::ensure_group`](crate::FCMDeviceGroupClient::ensure_group)
+#[derive(Debug, Error)]
+pub enum EnsureGroupError {
+    /// Creating the group failed
+    #[error("Error Creating Group")]
+    Create(#[source] FCMDeviceGroupsRequestError<operation_errors::CreateGroupError>),
+    /// Looking up the existing group failed
+    #[error("Error Looking Up Group")]
+    GetKey(#[source] FCMDeviceGroupsRequestError<operation_errors::GetKeyError>),
+    /// Adding the registration ids to the existing group failed
+    #[error("Error Adding To Group")]
+    ChangeMembers(#[source] FCMDeviceGroupsRequestError<operation_errors::ChangeGroupMembersError>),
+    /// The group kept being deleted and recreated by someone else
+    #[error("Group Changed Concurrently {attempts} Times")]
+    Contended {
+        /// Number of attempts made
+        attempts: u32,
+    },
+}
+
+/// Error changing the members of a [`ShardedGroup`](crate::sharding::ShardedGroup)
+#[derive(Debug, Error)]
+pub enum ShardedGroupError {
+    /// Creating a new shard failed
+    #[error("Error Creating Shard")]
+    Ensure(#[from] EnsureGroupError),
+    /// Adding or removing members of a shard failed
+    #[error("Error Changing Shard Members")]
+    ChangeMembers(#[source] FCMDeviceGroupsRequestError<operation_errors::ChangeGroupMembersError>),
+    /// The name of a new shard is not a valid notification key name
+    #[error("Invalid Shard Name")]
+    InvalidShardName(#[from] InvalidIdentifierError),
+}
+
+/// Error subscribing or unsubscribing registration tokens with
+/// [`FCMTopicClient`](crate::topic::FCMTopicClient).
+///
+/// Tokens are sent in batches, so the results of the batches that succeeded before the failure are kept.
+#[derive(Debug, Error)]
+#[error("Topic Batch Of {} Registration Tokens Failed", .failed.len())]
+pub struct TopicBatchFailure {
+    /// Results of the batches that succeeded, in order
+    pub applied: Vec<TopicResult>,
+    /// Tokens of the batch that failed
+    pub failed: Vec<RegistrationToken>,
+    /// Tokens of the batches that were not attempted
+    pub remaining: Vec<RegistrationToken>,
+    /// Why the batch failed
+    #[source]
+    pub source: TopicBatchError,
+}
+
+/// Why a batch of a topic subscription change failed
+#[derive(Debug, Error)]
+pub enum TopicBatchError {
+    /// The request failed
+    #[error("Error Making Topic Request")]
+    Request(#[from] FCMDeviceGroupsRequestError<FCMDeviceGroupsBadRequest>),
+    /// FCM returned a different number of results than tokens were sent, so they can't be matched up
+    #[error("Expected {expected} Topic Results, Got {actual}")]
+    ResultCountMismatch {
+        /// Number of tokens sent
+        expected: usize,
+        /// Number of results returned
+        actual: usize,
+    },
+}
+
+/// Error getting an access token with gcloud user credentials
+#[derive(Debug, Error)]
+pub enum AuthorizedUserError {
+    /// The credentials file could not be read
+    #[error("Error Reading User Credentials")]
+    Io(#[from] std::io::Error),
+    /// The credentials are not valid authorized user JSON
+    #[error("Invalid User Credentials")]
+    InvalidCredentials(#[from] serde_json::Error),
+    /// The token URI could not be parsed
+    #[error("Invalid Token URI")]
+    InvalidTokenUri(#[source] reqwest::Error),
+    /// The token request failed
+    #[error("Error Requesting Access Token")]
+    HttpError(#[from] reqwest::Error),
+    /// The token endpoint rejected the refresh token
+    #[error("Token Endpoint Returned {status}: {body}")]
+    TokenResponse {
+        /// Status of the response
+        status: StatusCode,
+        /// Body of the response
+        body: String,
+    },
+}
+
+/// Error talking to the GCE metadata server
+#[derive(Debug, Error)]
+pub enum MetadataServerError {
+    /// The request to the metadata server failed
+    #[error("Error Making HTTP Request with the Metadata Server")]
+    HttpError(#[from] reqwest::Error),
+    /// The metadata server returned an error
+    #[error("Metadata Server Returned {status}: {body}")]
+    ErrorResponse {
+        /// Status of the response
+        status: StatusCode,
+        /// Body of the response
+        body: String,
+    },
+    /// The response is missing the `Metadata-Flavor: Google` header, so it did not come from a metadata server
+    #[error("Response Is Not From a Metadata Server")]
+    NotMetadataServer,
+}
+
+/// Error getting an access token with a service account key
+#[cfg(feature = "service-account")]
+#[derive(Debug, Error)]
+pub enum ServiceAccountError {
+    /// The key file could not be read
+    #[error("Error Reading Service Account Key")]
+    Io(#[from] std::io::Error),
+    /// The key is not valid service account JSON
+    #[error("Invalid Service Account Key")]
+    InvalidKey(#[from] serde_json::Error),
+    /// The private key is not a PEM encoded PKCS#8 RSA key
+    #[error("Invalid Service Account Private Key")]
+    InvalidPrivateKey,
+    /// The token URI could not be parsed
+    #[error("Invalid Token URI")]
+    InvalidTokenUri(#[source] reqwest::Error),
+    /// Signing the JWT assertion failed
+    #[error("Error Signing JWT Assertion")]
+    Signing,
+    /// The token request failed
+    #[error("Error Requesting Access Token")]
+    HttpError(#[from] reqwest::Error),
+    /// The token endpoint rejected the assertion
+    #[error("Token Endpoint Returned {status}: {body}")]
+    TokenResponse {
+        /// Status of the response
+        status: StatusCode,
+        /// Body of the response
+        body: String,
+    },
+}
+
+/// A place Application Default Credentials were looked for, and why they were not used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CredentialsAttempt {
+    /// Where the credentials were looked for
+    pub source: CredentialsSource,
+    /// Why the credentials were not used
+    pub reason: String,
+}
+
+impl Display for CredentialsAttempt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.source, self.reason)
+    }
+}
+
+/// Error finding Application Default Credentials or creating a client with them
+#[derive(Debug, Error)]
+pub enum DefaultCredentialsError {
+    /// No usable credentials were found in any of the places tried
+    #[error("No Usable Application Default Credentials, Tried {}", describe_attempts(.0))]
+    NoCredentials(Vec<CredentialsAttempt>),
+    /// The sender id could not be derived from the credentials. Set `FCM_SENDER_ID` to the project number to
+    /// provide it.
+    #[error("No Sender ID For Credentials From {0}, Set FCM_SENDER_ID To The Project Number")]
+    MissingSenderId(CredentialsSource),
+    /// `FCM_SENDER_ID` is not a project number
+    #[error("FCM_SENDER_ID {0:?} Is Not A Project Number")]
+    InvalidSenderId(String),
+    /// The client could not be created
+    #[error("Error Creating Client")]
+    ClientCreation(#[from] FCMDeviceGroupClientCreationError),
+}
+
+fn describe_attempts(attempts: &[CredentialsAttempt]) -> String {
+    attempts
+        .iter()
+        .map(CredentialsAttempt::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A notification key name, notification key or registration token that failed validation
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidIdentifierError {
+    /// The value was empty
+    #[error("The {kind} is empty")]
+    Empty {
+        /// What the value was for
+        kind: &'static str,
+    },
+    /// The value was longer than allowed
+    #[error("The {kind} is longer than {max_len} bytes")]
+    TooLong {
+        /// What the value was for
+        kind: &'static str,
+        /// Maximum length in bytes
+        max_len: usize,
+    },
+    /// The value contained a character that is not allowed
+    #[error("The {kind} contains the invalid character {character:?}")]
+    InvalidCharacter {
+        /// What the value was for
+        kind: &'static str,
+        /// The first invalid character
+        character: char,
+    },
+}
+
+/// Describe an error along with all of its sources
+pub(crate) fn describe(error: &(dyn std::error::Error + '_)) -> String {
+    let mut description = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        description.push_str(": ");
+        description.push_str(&error.to_string());
+        source = error.source();
+    }
+    description
+}
+
+/// Progress of a call whose registration ids were split into several batches, when one of the batches failed
+#[derive(Debug, Error)]
+#[error("Batch {} of {} Failed", .applied.len() + 1, .applied.len() + 1 + .remaining.len())]
+pub struct PartialBatchFailure {
+    /// The group as of the last batch that succeeded
+    pub group: FCMDeviceGroup,
+    /// Batches that were applied, in order
+    pub applied: Vec<Vec<RegistrationToken>>,
+    /// The batch that failed
+    pub failed: Vec<RegistrationToken>,
+    /// Batches that were not attempted
+    pub remaining: Vec<Vec<RegistrationToken>>,
+    /// Error returned for the failed batch
+    #[source]
+    pub source: FCMDeviceGroupsRequestError<operation_errors::ChangeGroupMembersError>,
+}
+
+/// A non success response returned by FCM
+#[derive(Debug, Clone)]
+pub struct FCMErrorResponse {
+    /// HTTP status of the response
+    pub status: StatusCode,
+    /// Raw response body
+    pub body: String,
+    /// Error message parsed from the body, if it was JSON with an `error` field
+    pub error: Option<String>,
+}
+
+impl Display for FCMErrorResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(error) => write!(f, "{}: {error}", self.status),
+            None => write!(f, "{}: {}", self.status, self.body),
         }
     }
 }
 
+/// Extract the message from an FCM error body.
+///
+/// The device group API returns `{"error": "message"}` while newer Google APIs return
+/// `{"error": {"message": "message", ...}}`.
+fn parse_error_message(body: &str) -> Option<String> {
+    let value = serde_json::from_str::<serde_json::Value>(body).ok()?;
+    match value.get("error")? {
+        serde_json::Value::String(error) => Some(error.clone()),
+        error => error
+            .get("message")
+            .and_then(serde_json::Value::as_str)
+            .map(str::to_owned),
+    }
+}
+
 /// Bad Request Error from FCM
 #[derive(Debug, Deserialize, Error)]
 pub struct FCMDeviceGroupsBadRequest {
@@ -85,11 +460,11 @@ pub mod operation_errors {
 
     use crate::error::FCMDeviceGroupError;
 
-    const ALREADY_EXISTS_MESSAGE: &str = "notification_key already exists";
-    const NO_REGISTRATION_ID_MESSAGE: &str = "no valid registration ids";
-    const KEY_NAME_AND_KEY_DONT_MATCH: &str =
+    pub(crate) const ALREADY_EXISTS_MESSAGE: &str = "notification_key already exists";
+    pub(crate) const NO_REGISTRATION_ID_MESSAGE: &str = "no valid registration ids";
+    pub(crate) const KEY_NAME_AND_KEY_DONT_MATCH: &str =
         "notification_key_name doesn't match the group name of the notification_key";
-    const KEY_NOT_FOUND: &str = "notification_key not found";
+    pub(crate) const KEY_NOT_FOUND: &str = "notification_key not found";
 
     pub type OperationResult<T, E> = Result<T, super::FCMDeviceGroupsRequestError<E>>;
 
@@ -109,6 +484,13 @@ pub mod operation_errors {
                 _ => None,
             }
         }
+
+        fn kind(&self) -> &'static str {
+            match self {
+                Self::AlreadyExists => "already_exists",
+                Self::NoValidRegistrationIds => "no_valid_registration_ids",
+            }
+        }
     }
 
     #[derive(Debug, Error)]
@@ -130,6 +512,14 @@ pub mod operation_errors {
                 _ => None,
             }
         }
+
+        fn kind(&self) -> &'static str {
+            match self {
+                Self::NoValidRegistrationIds => "no_valid_registration_ids",
+                Self::KeyNameAndKeyDontMatch => "key_name_and_key_dont_match",
+                Self::KeyNotFound => "key_not_found",
+            }
+        }
     }
 
     #[derive(Debug, Error)]
@@ -144,6 +534,12 @@ pub mod operation_errors {
                 _ => None,
             }
         }
+
+        fn kind(&self) -> &'static str {
+            match self {
+                Self::KeyNotFound => "key_not_found",
+            }
+        }
     }
 }
 
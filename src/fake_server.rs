@@ -0,0 +1,313 @@
+//! An in-process stand-in for the FCM device group endpoint.
+//!
+//! [`FakeFCMServer`] speaks the same `/fcm/notification` protocol as FCM, keeps every group in memory and
+//! answers with the same error strings FCM uses, so [`FCMDeviceGroupClient`](crate::FCMDeviceGroupClient)
+//! can be exercised end to end with [`FCMDeviceGroupClient::with_url`](crate::FCMDeviceGroupClient::with_url)
+//! and no network access.
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    Method, Request, Response, StatusCode,
+    body::{Bytes, Incoming},
+    header,
+    server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use reqwest::Url;
+use serde::Serialize;
+use tokio::{net::TcpListener, task::JoinHandle};
+
+use crate::{
+    NotificationKey, NotificationKeyName, Operation, OperationResponse, RegistrationToken,
+    error::operation_errors::{
+        ALREADY_EXISTS_MESSAGE, KEY_NAME_AND_KEY_DONT_MATCH, KEY_NOT_FOUND,
+        NO_REGISTRATION_ID_MESSAGE,
+    },
+    sharding::MAX_GROUP_MEMBERS,
+};
+
+/// Path FCM serves device group operations on
+pub const NOTIFICATION_PATH: &str = "/fcm/notification";
+
+/// Error returned when a group would have more than [`MAX_GROUP_MEMBERS`] members. FCM doesn't document the
+/// wording of this error, so clients see it as an unrecognized error response.
+pub const TOO_MANY_MEMBERS_MESSAGE: &str = "too many registration ids in the group";
+
+/// A device group as stored by the fake server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FakeGroup {
+    /// Name of the device group
+    pub notification_key_name: NotificationKeyName,
+    /// Every key handed out for this group, oldest first
+    pub notification_keys: Vec<NotificationKey>,
+    /// Registration ids currently in the group
+    pub registration_ids: BTreeSet<RegistrationToken>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    groups: HashMap<NotificationKeyName, FakeGroup>,
+    keys: HashMap<NotificationKey, NotificationKeyName>,
+    next_key: u64,
+}
+
+/// Local HTTP server implementing the FCM device group protocol.
+///
+/// The server runs on the current tokio runtime and is shut down when dropped.
+pub struct FakeFCMServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl FakeFCMServer {
+    /// Start a server on an ephemeral port of `127.0.0.1`
+    pub async fn start() -> std::io::Result<Self> {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    /// Start a server on the given address
+    pub async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        log::warn!("Fake FCM server failed to accept connection: {e}");
+                        continue;
+                    }
+                };
+                let state = task_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| handle(state.clone(), request));
+                    if let Err(e) = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                    {
+                        log::warn!("Fake FCM server connection error: {e}");
+                    }
+                });
+            }
+        });
+
+        Ok(Self { addr, state, task })
+    }
+
+    /// Address the server is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL to pass to [`FCMDeviceGroupClient::with_url`](crate::FCMDeviceGroupClient::with_url)
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}{NOTIFICATION_PATH}", self.addr))
+            .expect("A socket address always forms a valid url")
+    }
+
+    /// Look up a group by name
+    pub fn group(&self, notification_key_name: &str) -> Option<FakeGroup> {
+        self.state
+            .lock()
+            .unwrap()
+            .groups
+            .get(notification_key_name)
+            .cloned()
+    }
+
+    /// All groups currently known to the server
+    pub fn groups(&self) -> Vec<FakeGroup> {
+        self.state
+            .lock()
+            .unwrap()
+            .groups
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+impl Drop for FakeFCMServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: &'a str,
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path() != NOTIFICATION_PATH {
+        return Ok(empty(StatusCode::NOT_FOUND));
+    }
+
+    let result = match *request.method() {
+        Method::GET => {
+            let name = request.uri().query().and_then(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == "notification_key_name")
+                    .map(|(_, value)| value.into_owned())
+            });
+            match name {
+                Some(name) => state.lock().unwrap().get_key(&name),
+                None => Err(KEY_NOT_FOUND),
+            }
+        }
+        Method::POST => {
+            let body = match request.into_body().collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(_) => return Ok(empty(StatusCode::BAD_REQUEST)),
+            };
+            match serde_json::from_slice::<Operation>(&body) {
+                Ok(operation) => state.lock().unwrap().apply(operation),
+                Err(_) => return Ok(empty(StatusCode::BAD_REQUEST)),
+            }
+        }
+        _ => return Ok(empty(StatusCode::METHOD_NOT_ALLOWED)),
+    };
+
+    Ok(match result {
+        Ok(key) => json(
+            StatusCode::OK,
+            &OperationResponse {
+                notification_key: key,
+            },
+        ),
+        Err(error) => json(StatusCode::BAD_REQUEST, &ErrorBody { error }),
+    })
+}
+
+fn empty(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+fn json(status: StatusCode, body: &impl Serialize) -> Response<Full<Bytes>> {
+    let body = serde_json::to_vec(body).expect("Response bodies always serialize");
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+impl State {
+    fn get_key(&self, name: &str) -> Result<NotificationKey, &'static str> {
+        self.groups
+            .get(name)
+            .and_then(|group| group.notification_keys.last().cloned())
+            .ok_or(KEY_NOT_FOUND)
+    }
+
+    fn apply(&mut self, operation: Operation) -> Result<NotificationKey, &'static str> {
+        match operation {
+            Operation::Create {
+                notification_key_name,
+                registration_ids,
+            } => {
+                if self.groups.contains_key(&notification_key_name) {
+                    return Err(ALREADY_EXISTS_MESSAGE);
+                }
+                if registration_ids.is_empty() {
+                    return Err(NO_REGISTRATION_ID_MESSAGE);
+                }
+                let registration_ids: BTreeSet<_> = registration_ids.into_iter().collect();
+                if registration_ids.len() > MAX_GROUP_MEMBERS {
+                    return Err(TOO_MANY_MEMBERS_MESSAGE);
+                }
+                self.next_key += 1;
+                let key = NotificationKey::new(format!("fake-notification-key-{}", self.next_key))
+                    .expect("Generated keys are valid");
+                self.keys.insert(key.clone(), notification_key_name.clone());
+                self.groups.insert(
+                    notification_key_name.clone(),
+                    FakeGroup {
+                        notification_key_name,
+                        notification_keys: vec![key.clone()],
+                        registration_ids,
+                    },
+                );
+                Ok(key)
+            }
+            Operation::Add {
+                notification_key_name,
+                notification_key,
+                registration_ids,
+            } => {
+                let group =
+                    self.group_for_key(notification_key_name.as_ref(), &notification_key)?;
+                if registration_ids.is_empty() {
+                    return Err(NO_REGISTRATION_ID_MESSAGE);
+                }
+                let new = registration_ids
+                    .iter()
+                    .filter(|id| !group.registration_ids.contains(*id))
+                    .collect::<BTreeSet<_>>()
+                    .len();
+                if group.registration_ids.len() + new > MAX_GROUP_MEMBERS {
+                    return Err(TOO_MANY_MEMBERS_MESSAGE);
+                }
+                group.registration_ids.extend(registration_ids);
+                Ok(notification_key)
+            }
+            Operation::Remove {
+                notification_key_name,
+                notification_key,
+                registration_ids,
+            } => {
+                let group =
+                    self.group_for_key(notification_key_name.as_ref(), &notification_key)?;
+                if !registration_ids
+                    .iter()
+                    .any(|id| group.registration_ids.contains(id))
+                {
+                    return Err(NO_REGISTRATION_ID_MESSAGE);
+                }
+                for id in &registration_ids {
+                    group.registration_ids.remove(id);
+                }
+                // FCM deletes a group once its last member is removed
+                if group.registration_ids.is_empty() {
+                    let name = group.notification_key_name.clone();
+                    if let Some(group) = self.groups.remove(&name) {
+                        for key in group.notification_keys {
+                            self.keys.remove(&key);
+                        }
+                    }
+                }
+                Ok(notification_key)
+            }
+        }
+    }
+
+    fn group_for_key(
+        &mut self,
+        notification_key_name: Option<&NotificationKeyName>,
+        notification_key: &NotificationKey,
+    ) -> Result<&mut FakeGroup, &'static str> {
+        let name = self.keys.get(notification_key).ok_or(KEY_NOT_FOUND)?;
+        if notification_key_name.is_some_and(|expected| expected != name) {
+            return Err(KEY_NAME_AND_KEY_DONT_MATCH);
+        }
+        self.groups.get_mut(name).ok_or(KEY_NOT_FOUND)
+    }
+}
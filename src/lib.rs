@@ -3,44 +3,93 @@
 //! See <https://firebase.google.com/docs/cloud-messaging/android/topic-messaging>
 //!
 //! Note that you will have to manually depend on a `reqwest` TLS feature if the default-tls feature is disabled.
+//!
+//! With the `tracing` feature each device group call runs in an `fcm_device_group` span. With the `metrics`
+//! feature calls are recorded through the `metrics` facade as `fcm_device_group_requests_total`,
+//! `fcm_device_group_request_duration_seconds` and `fcm_device_group_batch_size`, labelled by `operation` and, for
+//! the counter, `outcome`. Registration ids and notification keys are never recorded.
 pub use google_apis_common::{
     GetToken,
     auth::{GetTokenClone, NoToken},
 };
 use reqwest::{
     Client as HttpClient, IntoUrl, RequestBuilder, Response, Url,
-    header::{self, HeaderMap, HeaderValue},
+    header::{self, HeaderValue},
 };
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
 
+pub use api::DeviceGroupApi;
+pub use auth::CachedToken;
+pub use builder::FCMDeviceGroupClientBuilder;
+pub use ensure::EnsuredGroup;
 pub use raw::{Operation, OperationResponse};
+pub use reconcile::ReconcileReport;
+pub use registry::GroupRegistry;
+pub use retry::RetryPolicy;
+pub use types::{NotificationKey, NotificationKeyName, RegistrationToken};
 
+use batch::Batches;
 use error::operation_errors::OperationResult;
+use retry::Decision;
 
+mod api;
+pub mod auth;
+mod batch;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod ensure;
 pub mod error;
+#[cfg(feature = "fake-server")]
+pub mod fake_server;
+pub mod message;
+#[cfg(feature = "mock")]
+pub mod mock;
+mod observe;
 mod raw;
+mod reconcile;
+pub mod registry;
+pub mod retry;
+pub mod sharding;
+pub mod topic;
+pub mod types;
 
 /// Default URL used for FCM device groups
 pub const FIREBASE_NOTIFICATION_URL: &str = "https://fcm.googleapis.com/fcm/notification";
 
+/// Base URL of the FCM HTTP v1 API, which messages to device groups are sent with
+pub const FIREBASE_SEND_URL: &str = "https://fcm.googleapis.com/v1/";
+
+/// Default maximum number of registration ids sent in a single request.
+///
+/// FCM allows at most [`MAX_GROUP_MEMBERS`](sharding::MAX_GROUP_MEMBERS) registration ids in a device group, so a
+/// request can't usefully carry more. Use [`sharding`] for groups that need more members.
+pub const MAX_REGISTRATION_IDS_PER_REQUEST: usize = sharding::MAX_GROUP_MEMBERS;
+
 const FCM_DEVICE_GROUP_SCOPES: &[&str] = &["https://www.googleapis.com/auth/firebase.messaging"];
 
 /// Client to use fcm device groups
 #[derive(Clone)]
 pub struct FCMDeviceGroupClient {
     url: Url,
+    send_url: Option<Url>,
     client: HttpClient,
     auth: Box<dyn GetToken + 'static>,
+    retry_policy: RetryPolicy,
+    max_registration_ids: usize,
+    registry: Option<Arc<dyn GroupRegistry>>,
 }
 
 /// A Representation of an FCM Device group
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FCMDeviceGroup {
     /// Name of the device group
-    pub notification_key_name: String,
+    pub notification_key_name: NotificationKeyName,
     /// Key for this device group.
     ///
     /// Note that one device group may have multiple keys
-    pub notification_key: String,
+    pub notification_key: NotificationKey,
 }
 
 impl FCMDeviceGroupClient {
@@ -52,41 +101,78 @@ impl FCMDeviceGroupClient {
         Self::with_url(FIREBASE_NOTIFICATION_URL, sender_id, auth)
     }
 
+    /// Creates a new `FCMDeviceGroupClient` with the default url and Application Default Credentials.
+    ///
+    /// See [`DefaultCredentials`](auth::DefaultCredentials) for where credentials and the sender id are looked
+    /// for. Use [`builder_from_env`](Self::builder_from_env) to customize the client.
+    pub async fn from_env() -> Result<Self, error::DefaultCredentialsError> {
+        Ok(Self::builder_from_env().await?.build()?)
+    }
+
     /// Creates a new `FCMDeviceGroupClient` with the given url and the provided bearer auth string
     pub fn with_url(
         url: impl IntoUrl,
         sender_id: &str,
         auth: impl GetToken + 'static,
     ) -> Result<Self, error::FCMDeviceGroupClientCreationError> {
-        let mut headers = HeaderMap::new();
-        headers.insert("project_id", header::HeaderValue::try_from(sender_id)?);
-        headers.insert(
-            "access_token_auth",
-            header::HeaderValue::from_static("true"),
-        );
-
-        Ok(Self {
-            url: url.into_url().unwrap(),
-            client: HttpClient::builder()
-                .default_headers(headers)
-                .connection_verbose(true)
-                .build()?,
-            auth: Box::new(auth),
-        })
+        Self::builder(sender_id, auth).url(url).build()
     }
 
     /// Creates a new `FCMDeviceGroupClient` with the given url and client. Note that the creator of the client
-    /// is responsible for adding authorization headers
+    /// is responsible for adding authorization headers. Use [`builder`](Self::builder) to customize the http client
+    /// while keeping the required headers.
     pub fn with_client(
         client: HttpClient,
         url: impl IntoUrl,
         auth: impl GetToken + 'static,
-    ) -> Self {
-        Self {
-            url: url.into_url().unwrap(),
+    ) -> Result<Self, error::FCMDeviceGroupClientCreationError> {
+        Ok(Self {
+            url: url
+                .into_url()
+                .map_err(error::FCMDeviceGroupClientCreationError::InvalidUrl)?,
+            send_url: None,
             client,
             auth: Box::new(auth),
-        }
+            retry_policy: RetryPolicy::disabled(),
+            max_registration_ids: MAX_REGISTRATION_IDS_PER_REQUEST,
+            registry: None,
+        })
+    }
+
+    /// Set the policy used to retry failed requests. By default requests are not retried.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Record every successful change to a group's membership in the given registry
+    pub fn with_registry(mut self, registry: Arc<dyn GroupRegistry>) -> Self {
+        self.registry = Some(registry);
+        self
+    }
+
+    /// The registry changes are recorded in, if one was set
+    pub fn registry(&self) -> Option<&dyn GroupRegistry> {
+        self.registry.as_deref()
+    }
+
+    /// Set the url messages are sent to.
+    ///
+    /// Defaults to `projects/{sender_id}/messages:send` under [`FIREBASE_SEND_URL`]. Clients created with
+    /// [`with_client`](Self::with_client) don't know the sender id, so they need this to send messages.
+    pub fn with_send_url(mut self, send_url: Url) -> Self {
+        self.send_url = Some(send_url);
+        self
+    }
+
+    /// Set the maximum number of registration ids sent in a single request.
+    ///
+    /// Larger lists passed to [`create_group`](Self::create_group), [`add_to_group`](Self::add_to_group) and
+    /// [`remove_from_group`](Self::remove_from_group) are split into batches of this size and applied in order.
+    /// Defaults to [`MAX_REGISTRATION_IDS_PER_REQUEST`], the most FCM allows in a group.
+    pub fn with_max_registration_ids_per_request(mut self, max_registration_ids: usize) -> Self {
+        self.max_registration_ids = max_registration_ids.max(1);
+        self
     }
 
     /// Apply the given operation with with the client.
@@ -97,82 +183,167 @@ impl FCMDeviceGroupClient {
         OperationResponse,
         error::FCMDeviceGroupsRequestError<error::FCMDeviceGroupsBadRequest>,
     > {
-        let response = self.apply_raw(operation).await?;
-        error::FCMDeviceGroupsRequestError::json_response(response).await
+        let observation = observe::Observation::start(
+            operation.kind(),
+            operation.notification_key_name(),
+            Some(operation.registration_ids().len()),
+        );
+        observation
+            .run(async {
+                let response = self
+                    .execute(
+                        || self.client.post(self.url.clone()).json(&operation),
+                        operation.is_idempotent(),
+                    )
+                    .await;
+                registry::record_operation(self.registry(), &operation, &response);
+                response
+            })
+            .await
     }
 
     /// Create a new group with the provided name and ID
+    ///
+    /// If there are more registration ids than fit in one request, the group is created with the first batch and
+    /// the rest are added afterwards.
     pub async fn create_group(
         &self,
-        notification_key_name: String,
-        registration_ids: Vec<String>,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
     ) -> OperationResult<FCMDeviceGroup, error::operation_errors::CreateGroupError> {
-        self.apply_operation(Operation::Create {
-            notification_key_name: notification_key_name.clone(),
-            registration_ids,
-        })
-        .await
+        let observation = observe::Observation::start(
+            "create",
+            Some(&notification_key_name),
+            Some(registration_ids.len()),
+        );
+        observation
+            .run(async {
+                let mut batches = Batches::new(registration_ids, self.max_registration_ids);
+                let first = batches.next_batch().unwrap_or_default();
+                let group = self
+                    .apply_operation(Operation::Create {
+                        notification_key_name,
+                        registration_ids: first.clone(),
+                    })
+                    .await?;
+                batches.applied(first);
+                self.apply_batches(group, batches, ChangeMembers::Add)
+                    .await
+                    .map_err(error::FCMDeviceGroupsRequestError::PartialBatchFailure)
+            })
+            .await
     }
 
     /// Add a set of registration IDS to the group
     pub async fn add_to_group(
         &self,
         group: FCMDeviceGroup,
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
-        self.apply_operation(Operation::Add {
-            notification_key_name: Some(group.notification_key_name),
-            notification_key: group.notification_key,
-            registration_ids,
-        })
-        .await
+        self.change_members(group, registration_ids, ChangeMembers::Add)
+            .await
     }
 
     /// Remove a set of registration IDS to the group
     pub async fn remove_from_group(
         &self,
         group: FCMDeviceGroup,
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
-        self.apply_operation(Operation::Remove {
-            notification_key_name: Some(group.notification_key_name),
-            notification_key: group.notification_key,
-            registration_ids,
-        })
-        .await
+        self.change_members(group, registration_ids, ChangeMembers::Remove)
+            .await
+    }
+
+    /// Add registration tokens to the group, updating its notification key in place.
+    ///
+    /// Unlike [`add_to_group`](Self::add_to_group) the group is borrowed, so the same handle can be reused across
+    /// calls. If a later batch fails, the group is still updated with the key from the last batch that succeeded.
+    pub async fn add_members<I>(
+        &self,
+        group: &mut FCMDeviceGroup,
+        registration_ids: I,
+    ) -> OperationResult<(), error::operation_errors::ChangeGroupMembersError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.change_members_in_place(group, registration_ids, ChangeMembers::Add)
+            .await
+    }
+
+    /// Remove registration tokens from the group, updating its notification key in place.
+    ///
+    /// Unlike [`remove_from_group`](Self::remove_from_group) the group is borrowed, so the same handle can be
+    /// reused across calls. If a later batch fails, the group is still updated with the key from the last batch
+    /// that succeeded.
+    pub async fn remove_members<I>(
+        &self,
+        group: &mut FCMDeviceGroup,
+        registration_ids: I,
+    ) -> OperationResult<(), error::operation_errors::ChangeGroupMembersError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        self.change_members_in_place(group, registration_ids, ChangeMembers::Remove)
+            .await
     }
 
     /// Use this client to request the notification key for a given name
     pub async fn get_key(
         &self,
-        notification_key_name: String,
+        notification_key_name: NotificationKeyName,
     ) -> OperationResult<FCMDeviceGroup, error::operation_errors::GetKeyError> {
-        let request = self
-            .client
-            .get(self.url.clone())
-            .query(&[("notification_key_name", notification_key_name.as_str())])
-            .header(
-                header::CONTENT_TYPE,
-                HeaderValue::from_static("application/json"),
-            );
-        let response = self
-            .add_token(request)
+        let observation =
+            observe::Observation::start("get_key", Some(&notification_key_name), None);
+        observation
+            .run(async {
+                let request = || {
+                    self.client
+                        .get(self.url.clone())
+                        .query(&[("notification_key_name", notification_key_name.as_str())])
+                        .header(
+                            header::CONTENT_TYPE,
+                            HeaderValue::from_static("application/json"),
+                        )
+                };
+                let response: OperationResponse = self.execute(request, true).await?;
+                Ok(FCMDeviceGroup {
+                    notification_key_name,
+                    notification_key: response.notification_key,
+                })
+            })
             .await
-            .map_err(error::RawError::GetTokenError)?
-            .send()
-            .await?;
-        let response =
-            error::FCMDeviceGroupsRequestError::<error::operation_errors::GetKeyError>::json_response::<OperationResponse>(response)
-                .await?;
-        Ok(FCMDeviceGroup {
-            notification_key_name,
-            notification_key: response.notification_key,
-        })
     }
 
-    async fn apply_raw(&self, operation: Operation) -> Result<Response, error::RawError> {
-        let request = self.client.post(self.url.clone()).json(&operation);
+    /// Send a message to every device in the group
+    pub async fn send(
+        &self,
+        group: &FCMDeviceGroup,
+        message: &message::Message,
+    ) -> OperationResult<message::GroupSendResponse, error::FCMDeviceGroupsBadRequest> {
+        self.send_to_key(&group.notification_key, message).await
+    }
+
+    /// Send a message to every device in the group with the given notification key.
+    ///
+    /// The message is sent with the FCM HTTP v1 API, addressed to the notification key as its token.
+    pub async fn send_to_key(
+        &self,
+        notification_key: &NotificationKey,
+        message: &message::Message,
+    ) -> OperationResult<message::GroupSendResponse, error::FCMDeviceGroupsBadRequest> {
+        let send_url = self
+            .send_url
+            .as_ref()
+            .ok_or(error::FCMDeviceGroupsRequestError::MissingSendUrl)?;
+        let request = message::SendRequest::new(notification_key, message);
+        // Sending twice would deliver the message twice
+        self.execute(|| self.client.post(send_url.clone()).json(&request), false)
+            .await
+    }
 
+    async fn send_raw(&self, request: RequestBuilder) -> Result<Response, error::RawError> {
         let request = self
             .add_token(request)
             .await
@@ -181,6 +352,44 @@ impl FCMDeviceGroupClient {
         Ok(request.send().await?)
     }
 
+    /// Send the request built by `request`, retrying according to the client's [`RetryPolicy`].
+    ///
+    /// `idempotent` marks requests that can be repeated safely even if FCM may have already processed them.
+    async fn execute<T: DeserializeOwned, E: error::FCMDeviceGroupError>(
+        &self,
+        request: impl Fn() -> RequestBuilder,
+        idempotent: bool,
+    ) -> OperationResult<T, E> {
+        let max_attempts = self.retry_policy.max_attempts();
+        let mut attempt = 1;
+        loop {
+            log::debug!("Sending FCM request, attempt {attempt}/{max_attempts}");
+            let result = self.send_raw(request()).await;
+            let outcome = result
+                .as_ref()
+                .map(|response| (response.status(), response.headers()));
+            let decision = self.retry_policy.decide(outcome, attempt, idempotent);
+            if let Decision::Retry(delay) = decision {
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+                continue;
+            }
+            let result = match result {
+                Ok(response) => {
+                    let status = response.status();
+                    match response.bytes().await {
+                        Ok(body) => {
+                            error::FCMDeviceGroupsRequestError::from_response(status, &body)
+                        }
+                        Err(e) => Err(e.into()),
+                    }
+                }
+                Err(e) => Err(e.into()),
+            };
+            return decision.finish(result, attempt);
+        }
+    }
+
     async fn add_token(
         &self,
         request: RequestBuilder,
@@ -191,37 +400,152 @@ impl FCMDeviceGroupClient {
         }
     }
 
-    async fn apply_operation<E: error::FCMDeviceGroupError>(
+    async fn change_members(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+        change: ChangeMembers,
+    ) -> OperationResult<FCMDeviceGroup, error::operation_errors::ChangeGroupMembersError> {
+        let observation = observe::Observation::start(
+            change.kind(),
+            Some(&group.notification_key_name),
+            Some(registration_ids.len()),
+        );
+        observation
+            .run(async {
+                let mut batches = Batches::new(registration_ids, self.max_registration_ids);
+                let first = batches.next_batch().unwrap_or_default();
+                let group = self
+                    .apply_operation(change.operation(group, first.clone()))
+                    .await?;
+                batches.applied(first);
+                self.apply_batches(group, batches, change)
+                    .await
+                    .map_err(error::FCMDeviceGroupsRequestError::PartialBatchFailure)
+            })
+            .await
+    }
+
+    async fn change_members_in_place<I>(
+        &self,
+        group: &mut FCMDeviceGroup,
+        registration_ids: I,
+        change: ChangeMembers,
+    ) -> OperationResult<(), error::operation_errors::ChangeGroupMembersError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let registration_ids = registration_ids
+            .into_iter()
+            .map(|id| RegistrationToken::new(id.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(error::FCMDeviceGroupsRequestError::InvalidRegistrationToken)?;
+        match self
+            .change_members(group.clone(), registration_ids, change)
+            .await
+        {
+            Ok(updated) => {
+                *group = updated;
+                Ok(())
+            }
+            Err(error::FCMDeviceGroupsRequestError::PartialBatchFailure(failure)) => {
+                *group = failure.group.clone();
+                Err(error::FCMDeviceGroupsRequestError::PartialBatchFailure(
+                    failure,
+                ))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Apply the batches left after the first one was applied to `group`
+    async fn apply_batches(
+        &self,
+        mut group: FCMDeviceGroup,
+        mut batches: Batches,
+        change: ChangeMembers,
+    ) -> Result<FCMDeviceGroup, Box<error::PartialBatchFailure>> {
+        while let Some(batch) = batches.next_batch() {
+            let operation = change.operation(group.clone(), batch.clone());
+            match self.apply_operation(operation).await {
+                Ok(updated) => {
+                    group = updated;
+                    batches.applied(batch);
+                }
+                Err(source) => return Err(batches.failed(group, batch, source)),
+            }
+        }
+        Ok(group)
+    }
+
+    async fn apply_operation<E: error::FCMDeviceGroupError + 'static>(
         &self,
         operation: Operation,
     ) -> OperationResult<FCMDeviceGroup, E> {
-        let key_name = match &operation {
-            Operation::Create {
-                notification_key_name,
-                ..
-            } => notification_key_name.to_owned(),
-            Operation::Add {
-                notification_key_name,
-                ..
-            } => notification_key_name
-                .as_ref()
-                .expect("Applying an operation should always have a key name")
-                .to_owned(),
-            Operation::Remove {
-                notification_key_name,
-                ..
-            } => notification_key_name
-                .as_ref()
-                .expect("Applying an operation should always have a key name")
-                .to_owned(),
-        };
-        let response = self.apply_raw(operation).await?;
-        let response =
-            error::FCMDeviceGroupsRequestError::<E>::json_response::<OperationResponse>(response)
-                .await?;
-        Ok(FCMDeviceGroup {
+        let key_name = operation
+            .notification_key_name()
+            .cloned()
+            .ok_or(error::FCMDeviceGroupsRequestError::MissingKeyName)?;
+        let response = self
+            .execute(
+                || self.client.post(self.url.clone()).json(&operation),
+                operation.is_idempotent(),
+            )
+            .await;
+        registry::record_operation(self.registry(), &operation, &response);
+        let response = response?;
+        let group = FCMDeviceGroup {
             notification_key_name: key_name,
             notification_key: response.notification_key,
-        })
+        };
+        registry::record_change(self.registry(), &group, &operation);
+        Ok(group)
+    }
+}
+
+/// Url messages are sent to for the given sender id
+fn default_send_url(sender_id: &str) -> Url {
+    let mut url = Url::parse(FIREBASE_SEND_URL).expect("The default send url is valid");
+    url.path_segments_mut()
+        .expect("The default send url has a path")
+        .pop_if_empty()
+        .extend(["projects", sender_id, "messages:send"]);
+    url
+}
+
+#[derive(Clone, Copy)]
+enum ChangeMembers {
+    Add,
+    Remove,
+}
+
+impl ChangeMembers {
+    fn kind(self) -> &'static str {
+        match self {
+            ChangeMembers::Add => "add",
+            ChangeMembers::Remove => "remove",
+        }
+    }
+
+    fn operation(
+        self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> Operation {
+        let notification_key_name = Some(group.notification_key_name);
+        let notification_key = group.notification_key;
+        match self {
+            ChangeMembers::Add => Operation::Add {
+                notification_key_name,
+                notification_key,
+                registration_ids,
+            },
+            ChangeMembers::Remove => Operation::Remove {
+                notification_key_name,
+                notification_key,
+                registration_ids,
+            },
+        }
     }
 }
@@ -0,0 +1,416 @@
+//! Messages sent to a device group.
+//! See <https://firebase.google.com/docs/cloud-messaging/android/device-group#sending_downstream_messages_to_device_groups>
+//!
+//! Messages are sent with the FCM HTTP v1 API, using the group's notification key as the message token.
+//!
+//! Platform overrides follow the FCM message schema, see
+//! <https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages>
+//!
+//! ```
+//! use fcm_device_group::message::{AndroidMessagePriority, Message};
+//!
+//! let message = Message::builder()
+//!     .title("Door opened")
+//!     .body("The front door was opened")
+//!     .data("door", "front")
+//!     .android_priority(AndroidMessagePriority::High)
+//!     .android_channel_id("alerts")
+//!     .apns_header("apns-priority", "10")
+//!     .build();
+//! ```
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::NotificationKey;
+
+/// Message payload sent to every device in a group
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Message {
+    /// Notification displayed by the device
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<Notification>,
+    /// Custom key value pairs delivered to the app
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub data: HashMap<String, String>,
+    /// Android specific options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub android: Option<AndroidConfig>,
+    /// Apple Push Notification Service specific options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apns: Option<ApnsConfig>,
+    /// Webpush specific options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webpush: Option<WebpushConfig>,
+}
+
+impl Message {
+    /// Start building a message
+    pub fn builder() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+}
+
+/// Basic notification shown on every platform
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Notification {
+    /// Title of the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Body text of the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// URL of an image shown in the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// Android specific options.
+/// See <https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#androidconfig>
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AndroidConfig {
+    /// Identifier of a group of messages that can be collapsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collapse_key: Option<String>,
+    /// Delivery priority
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<AndroidMessagePriority>,
+    /// How long the message is kept if the device is offline
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_duration"
+    )]
+    pub ttl: Option<Duration>,
+    /// Android specific notification options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<AndroidNotification>,
+}
+
+/// Delivery priority of an Android message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AndroidMessagePriority {
+    /// Delivered when the device is not in doze
+    Normal,
+    /// Delivered immediately, waking a sleeping device
+    High,
+}
+
+/// Android specific notification options
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AndroidNotification {
+    /// Notification channel the notification is posted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_id: Option<String>,
+    /// Drawable resource used as the icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// Icon color in `#rrggbb` format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Sound played when the notification is shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    /// Notifications with the same tag replace each other
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Intent action launched when the notification is clicked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub click_action: Option<String>,
+}
+
+/// Apple Push Notification Service specific options.
+/// See <https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#apnsconfig>
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApnsConfig {
+    /// APNs request headers such as `apns-priority` and `apns-expiration`
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// APNs payload
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<ApnsPayload>,
+}
+
+/// APNs payload
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApnsPayload {
+    /// The `aps` dictionary
+    pub aps: Aps,
+}
+
+/// The APNs `aps` dictionary.
+/// See <https://developer.apple.com/documentation/usernotifications/generating-a-remote-notification>
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Aps {
+    /// Alert shown to the user
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<ApsAlert>,
+    /// Number shown on the app icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<u32>,
+    /// Name of the sound file to play
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    /// Identifier used to group notifications
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    /// Notification category
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    /// Set to 1 to deliver a background update
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_available: Option<u8>,
+    /// Set to 1 to let a notification service extension modify the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutable_content: Option<u8>,
+}
+
+/// Alert in the APNs `aps` dictionary
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ApsAlert {
+    /// Title of the alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Subtitle of the alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    /// Body text of the alert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+/// Webpush specific options.
+/// See <https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#webpushconfig>
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebpushConfig {
+    /// Webpush protocol headers such as `TTL` and `Urgency`
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    /// Custom key value pairs, overriding [`Message::data`]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub data: HashMap<String, String>,
+    /// Web notification options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<WebpushNotification>,
+    /// Options for features provided by the FCM SDK for web
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<WebpushFcmOptions>,
+}
+
+/// Web notification options.
+/// See <https://developer.mozilla.org/en-US/docs/Web/API/Notification/Notification>
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebpushNotification {
+    /// Title of the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Body text of the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// URL of the notification icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// URL of an image shown in the notification
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Actions shown as buttons on the notification
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub actions: Vec<WebpushAction>,
+}
+
+/// Action shown on a web notification
+#[derive(Debug, Clone, Serialize)]
+pub struct WebpushAction {
+    /// Identifier of the action passed back to the service worker
+    pub action: String,
+    /// Text shown to the user
+    pub title: String,
+    /// URL of the action icon
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+/// Options for features provided by the FCM SDK for web
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebpushFcmOptions {
+    /// Link opened when the notification is clicked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+/// Builder for [`Message`]
+#[derive(Debug, Clone, Default)]
+pub struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    /// Set the notification title
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.notification().title = Some(title.into());
+        self
+    }
+
+    /// Set the notification body
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.notification().body = Some(body.into());
+        self
+    }
+
+    /// Set the notification image URL
+    pub fn image(mut self, image: impl Into<String>) -> Self {
+        self.notification().image = Some(image.into());
+        self
+    }
+
+    /// Add a custom key value pair
+    pub fn data(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.message.data.insert(key.into(), value.into());
+        self
+    }
+
+    /// Replace the Android options
+    pub fn android(mut self, android: AndroidConfig) -> Self {
+        self.message.android = Some(android);
+        self
+    }
+
+    /// Set the Android delivery priority
+    pub fn android_priority(mut self, priority: AndroidMessagePriority) -> Self {
+        self.android_config().priority = Some(priority);
+        self
+    }
+
+    /// Set how long the message is kept for offline Android devices
+    pub fn android_ttl(mut self, ttl: Duration) -> Self {
+        self.android_config().ttl = Some(ttl);
+        self
+    }
+
+    /// Set the Android notification channel
+    pub fn android_channel_id(mut self, channel_id: impl Into<String>) -> Self {
+        self.android_config()
+            .notification
+            .get_or_insert_default()
+            .channel_id = Some(channel_id.into());
+        self
+    }
+
+    /// Replace the APNs options
+    pub fn apns(mut self, apns: ApnsConfig) -> Self {
+        self.message.apns = Some(apns);
+        self
+    }
+
+    /// Add an APNs request header
+    pub fn apns_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.apns_config().headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Set the APNs `aps` dictionary
+    pub fn aps(mut self, aps: Aps) -> Self {
+        self.apns_config().payload = Some(ApnsPayload { aps });
+        self
+    }
+
+    /// Replace the Webpush options
+    pub fn webpush(mut self, webpush: WebpushConfig) -> Self {
+        self.message.webpush = Some(webpush);
+        self
+    }
+
+    /// Add a Webpush protocol header
+    pub fn webpush_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.webpush_config()
+            .headers
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// Add an action button to the web notification
+    pub fn webpush_action(mut self, action: WebpushAction) -> Self {
+        self.webpush_config()
+            .notification
+            .get_or_insert_default()
+            .actions
+            .push(action);
+        self
+    }
+
+    /// Finish building the message
+    pub fn build(self) -> Message {
+        self.message
+    }
+
+    fn notification(&mut self) -> &mut Notification {
+        self.message.notification.get_or_insert_default()
+    }
+
+    fn android_config(&mut self) -> &mut AndroidConfig {
+        self.message.android.get_or_insert_default()
+    }
+
+    fn apns_config(&mut self) -> &mut ApnsConfig {
+        self.message.apns.get_or_insert_default()
+    }
+
+    fn webpush_config(&mut self) -> &mut WebpushConfig {
+        self.message.webpush.get_or_insert_default()
+    }
+}
+
+/// Serialize a duration in the `"3.5s"` format used by FCM
+fn serialize_duration<S: Serializer>(
+    duration: &Option<Duration>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) if duration.subsec_nanos() == 0 => {
+            serializer.serialize_str(&format!("{}s", duration.as_secs()))
+        }
+        Some(duration) => {
+            let nanos = format!("{:09}", duration.subsec_nanos());
+            serializer.serialize_str(&format!(
+                "{}.{}s",
+                duration.as_secs(),
+                nanos.trim_end_matches('0')
+            ))
+        }
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Response from sending a message to a device group.
+///
+/// The HTTP v1 API only reports that FCM accepted the message, not how many devices of the group it reached.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct GroupSendResponse {
+    /// Identifier of the message, in the format `projects/*/messages/{message_id}`
+    pub name: String,
+}
+
+/// Body of an HTTP v1 send request
+#[derive(Serialize)]
+pub(crate) struct SendRequest<'a> {
+    message: AddressedMessage<'a>,
+}
+
+#[derive(Serialize)]
+struct AddressedMessage<'a> {
+    token: &'a NotificationKey,
+    #[serde(flatten)]
+    message: &'a Message,
+}
+
+impl<'a> SendRequest<'a> {
+    pub(crate) fn new(notification_key: &'a NotificationKey, message: &'a Message) -> Self {
+        Self {
+            message: AddressedMessage {
+                token: notification_key,
+                message,
+            },
+        }
+    }
+}
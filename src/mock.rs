@@ -0,0 +1,245 @@
+//! Mock implementation of [`DeviceGroupApi`] for tests.
+//!
+//! [`MockDeviceGroupApi`] records every call and answers with responses queued by the test. Calls without a
+//! queued response succeed: created groups get keys like `mock-notification-key-1` that [`get_key`] then
+//! returns, and adding or removing members returns the group unchanged.
+//!
+//! ```
+//! use fcm_device_group::{
+//!     DeviceGroupApi,
+//!     error::operation_errors::CreateGroupError,
+//!     mock::{MockCall, MockDeviceGroupApi},
+//! };
+//!
+//! # tokio_test();
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn tokio_test() {
+//! let mock = MockDeviceGroupApi::new();
+//! mock.queue_create_group(Err(CreateGroupError::AlreadyExists.into()));
+//!
+//! let name = "group".parse().unwrap();
+//! let tokens = vec!["token".parse().unwrap()];
+//! assert!(mock.create_group(name, tokens).await.is_err());
+//! assert!(matches!(mock.calls()[0], MockCall::CreateGroup { .. }));
+//! # }
+//! ```
+//!
+//! [`get_key`]: DeviceGroupApi::get_key
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    sync::Mutex,
+};
+
+use crate::{
+    DeviceGroupApi, FCMDeviceGroup, NotificationKey, NotificationKeyName, Operation,
+    OperationResponse, RegistrationToken,
+    error::{
+        FCMDeviceGroupsBadRequest,
+        operation_errors::{
+            ChangeGroupMembersError, CreateGroupError, GetKeyError, OperationResult,
+        },
+    },
+};
+
+/// A call made to a [`MockDeviceGroupApi`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall {
+    /// [`DeviceGroupApi::apply`]
+    Apply(Operation),
+    /// [`DeviceGroupApi::create_group`]
+    CreateGroup {
+        #[allow(missing_docs)]
+        notification_key_name: NotificationKeyName,
+        #[allow(missing_docs)]
+        registration_ids: Vec<RegistrationToken>,
+    },
+    /// [`DeviceGroupApi::add_to_group`]
+    AddToGroup {
+        #[allow(missing_docs)]
+        group: FCMDeviceGroup,
+        #[allow(missing_docs)]
+        registration_ids: Vec<RegistrationToken>,
+    },
+    /// [`DeviceGroupApi::remove_from_group`]
+    RemoveFromGroup {
+        #[allow(missing_docs)]
+        group: FCMDeviceGroup,
+        #[allow(missing_docs)]
+        registration_ids: Vec<RegistrationToken>,
+    },
+    /// [`DeviceGroupApi::get_key`]
+    GetKey(NotificationKeyName),
+}
+
+#[derive(Default)]
+struct MockState {
+    calls: Vec<MockCall>,
+    apply: VecDeque<OperationResult<OperationResponse, FCMDeviceGroupsBadRequest>>,
+    create_group: VecDeque<OperationResult<FCMDeviceGroup, CreateGroupError>>,
+    add_to_group: VecDeque<OperationResult<FCMDeviceGroup, ChangeGroupMembersError>>,
+    remove_from_group: VecDeque<OperationResult<FCMDeviceGroup, ChangeGroupMembersError>>,
+    get_key: VecDeque<OperationResult<FCMDeviceGroup, GetKeyError>>,
+    keys: HashMap<NotificationKeyName, NotificationKey>,
+    next_key: u64,
+}
+
+impl MockState {
+    fn create_key(&mut self, notification_key_name: &NotificationKeyName) -> NotificationKey {
+        self.next_key += 1;
+        let key = NotificationKey::new(format!("mock-notification-key-{}", self.next_key))
+            .expect("Generated keys are valid");
+        self.keys.insert(notification_key_name.clone(), key.clone());
+        key
+    }
+}
+
+/// [`DeviceGroupApi`] that records calls and returns queued responses
+#[derive(Default)]
+pub struct MockDeviceGroupApi {
+    state: Mutex<MockState>,
+}
+
+impl MockDeviceGroupApi {
+    /// Create a mock with no queued responses
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every call made so far, oldest first
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state().calls.clone()
+    }
+
+    /// Queue the response to the next [`apply`](DeviceGroupApi::apply) call
+    pub fn queue_apply(
+        &self,
+        response: OperationResult<OperationResponse, FCMDeviceGroupsBadRequest>,
+    ) {
+        self.state().apply.push_back(response);
+    }
+
+    /// Queue the response to the next [`create_group`](DeviceGroupApi::create_group) call
+    pub fn queue_create_group(&self, response: OperationResult<FCMDeviceGroup, CreateGroupError>) {
+        self.state().create_group.push_back(response);
+    }
+
+    /// Queue the response to the next [`add_to_group`](DeviceGroupApi::add_to_group) call
+    pub fn queue_add_to_group(
+        &self,
+        response: OperationResult<FCMDeviceGroup, ChangeGroupMembersError>,
+    ) {
+        self.state().add_to_group.push_back(response);
+    }
+
+    /// Queue the response to the next [`remove_from_group`](DeviceGroupApi::remove_from_group) call
+    pub fn queue_remove_from_group(
+        &self,
+        response: OperationResult<FCMDeviceGroup, ChangeGroupMembersError>,
+    ) {
+        self.state().remove_from_group.push_back(response);
+    }
+
+    /// Queue the response to the next [`get_key`](DeviceGroupApi::get_key) call
+    pub fn queue_get_key(&self, response: OperationResult<FCMDeviceGroup, GetKeyError>) {
+        self.state().get_key.push_back(response);
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl DeviceGroupApi for MockDeviceGroupApi {
+    fn apply(
+        &self,
+        operation: Operation,
+    ) -> impl Future<Output = OperationResult<OperationResponse, FCMDeviceGroupsBadRequest>> + Send
+    {
+        let mut state = self.state();
+        state.calls.push(MockCall::Apply(operation.clone()));
+        let response = state.apply.pop_front().unwrap_or_else(|| {
+            let notification_key = match operation {
+                Operation::Create {
+                    notification_key_name,
+                    ..
+                } => state.create_key(&notification_key_name),
+                Operation::Add {
+                    notification_key, ..
+                }
+                | Operation::Remove {
+                    notification_key, ..
+                } => notification_key,
+            };
+            Ok(OperationResponse { notification_key })
+        });
+        std::future::ready(response)
+    }
+
+    fn create_group(
+        &self,
+        notification_key_name: NotificationKeyName,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, CreateGroupError>> + Send {
+        let mut state = self.state();
+        state.calls.push(MockCall::CreateGroup {
+            notification_key_name: notification_key_name.clone(),
+            registration_ids,
+        });
+        let response = state.create_group.pop_front().unwrap_or_else(|| {
+            Ok(FCMDeviceGroup {
+                notification_key: state.create_key(&notification_key_name),
+                notification_key_name,
+            })
+        });
+        std::future::ready(response)
+    }
+
+    fn add_to_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send {
+        let mut state = self.state();
+        state.calls.push(MockCall::AddToGroup {
+            group: group.clone(),
+            registration_ids,
+        });
+        std::future::ready(state.add_to_group.pop_front().unwrap_or(Ok(group)))
+    }
+
+    fn remove_from_group(
+        &self,
+        group: FCMDeviceGroup,
+        registration_ids: Vec<RegistrationToken>,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, ChangeGroupMembersError>> + Send {
+        let mut state = self.state();
+        state.calls.push(MockCall::RemoveFromGroup {
+            group: group.clone(),
+            registration_ids,
+        });
+        std::future::ready(state.remove_from_group.pop_front().unwrap_or(Ok(group)))
+    }
+
+    fn get_key(
+        &self,
+        notification_key_name: NotificationKeyName,
+    ) -> impl Future<Output = OperationResult<FCMDeviceGroup, GetKeyError>> + Send {
+        let mut state = self.state();
+        state
+            .calls
+            .push(MockCall::GetKey(notification_key_name.clone()));
+        let response = state.get_key.pop_front().unwrap_or_else(|| {
+            let notification_key = state
+                .keys
+                .get(&notification_key_name)
+                .cloned()
+                .ok_or(GetKeyError::KeyNotFound)?;
+            Ok(FCMDeviceGroup {
+                notification_key_name,
+                notification_key,
+            })
+        });
+        std::future::ready(response)
+    }
+}
@@ -0,0 +1,146 @@
+//! Instrumentation of client calls.
+//!
+//! With the `tracing` feature every call runs in an `fcm_device_group` span recording the operation, group
+//! name, number of registration ids, last HTTP status, latency and error kind. Registration ids and
+//! notification keys are never recorded.
+//!
+//! With the `metrics` feature every call is recorded through the [`metrics`] facade, labelled with its
+//! `operation` (`create`, `add`, `remove` or `get_key`):
+//!
+//! - `fcm_device_group_requests_total` counts calls by `operation` and `outcome`, which is `success` or the
+//!   kind of error, such as `already_exists`, `key_not_found`, `client_error` or `server_error`
+//! - `fcm_device_group_request_duration_seconds` is a histogram of call latency, including retries
+//! - `fcm_device_group_batch_size` is a histogram of the number of registration ids per call that changes a
+//!   group
+use std::future::Future;
+
+#[cfg(any(feature = "tracing", feature = "metrics"))]
+use std::time::Instant;
+
+#[cfg(feature = "tracing")]
+use tracing::{Instrument, Span, field};
+
+use crate::{
+    NotificationKeyName,
+    error::{FCMDeviceGroupError, operation_errors::OperationResult},
+};
+
+/// A client call in progress
+pub(crate) struct Observation {
+    #[cfg(feature = "tracing")]
+    span: Span,
+    #[cfg(feature = "metrics")]
+    operation: &'static str,
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    start: Instant,
+}
+
+impl Observation {
+    /// Start observing a call to `operation`.
+    ///
+    /// The group name is only traced, as a metric label it would make the cardinality unbounded.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn start(
+        operation: &'static str,
+        notification_key_name: Option<&NotificationKeyName>,
+        registration_ids: Option<usize>,
+    ) -> Self {
+        #[cfg(feature = "metrics")]
+        if let Some(registration_ids) = registration_ids {
+            metrics::histogram!("fcm_device_group_batch_size", "operation" => operation)
+                .record(registration_ids as f64);
+        }
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "fcm_device_group",
+                operation,
+                notification_key_name = notification_key_name.map(NotificationKeyName::as_str),
+                registration_ids,
+                status = field::Empty,
+                latency_ms = field::Empty,
+                error = field::Empty,
+            ),
+            #[cfg(feature = "metrics")]
+            operation,
+            #[cfg(any(feature = "tracing", feature = "metrics"))]
+            start: Instant::now(),
+        }
+    }
+
+    /// Run the call
+    pub(crate) async fn run<T, E: FCMDeviceGroupError>(
+        self,
+        call: impl Future<Output = OperationResult<T, E>>,
+    ) -> OperationResult<T, E> {
+        #[cfg(feature = "tracing")]
+        let result = call.instrument(self.span.clone()).await;
+        #[cfg(not(feature = "tracing"))]
+        let result = call.await;
+        self.finish(&result);
+        result
+    }
+
+    /// Run a blocking call
+    #[cfg(feature = "blocking")]
+    pub(crate) fn run_blocking<T, E: FCMDeviceGroupError>(
+        self,
+        call: impl FnOnce() -> OperationResult<T, E>,
+    ) -> OperationResult<T, E> {
+        let result = {
+            #[cfg(feature = "tracing")]
+            let _entered = self.span.enter();
+            call()
+        };
+        self.finish(&result);
+        result
+    }
+
+    #[cfg_attr(
+        not(any(feature = "tracing", feature = "metrics")),
+        allow(unused_variables)
+    )]
+    fn finish<T, E: FCMDeviceGroupError>(self, result: &OperationResult<T, E>) {
+        #[cfg(any(feature = "tracing", feature = "metrics"))]
+        let latency = self.start.elapsed();
+
+        #[cfg(feature = "metrics")]
+        {
+            let outcome = match result {
+                Ok(_) => "success",
+                Err(e) => e.kind(),
+            };
+            metrics::counter!(
+                "fcm_device_group_requests_total",
+                "operation" => self.operation,
+                "outcome" => outcome,
+            )
+            .increment(1);
+            metrics::histogram!(
+                "fcm_device_group_request_duration_seconds",
+                "operation" => self.operation,
+            )
+            .record(latency.as_secs_f64());
+        }
+
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("latency_ms", latency.as_millis() as u64);
+            let _entered = self.span.enter();
+            match result {
+                Ok(_) => tracing::debug!("FCM call succeeded"),
+                Err(e) => {
+                    self.span.record("error", e.kind());
+                    tracing::debug!(error = %e, "FCM call failed");
+                }
+            }
+        }
+    }
+}
+
+/// Record the status of a response on the current call
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record_status(status: reqwest::StatusCode) {
+    #[cfg(feature = "tracing")]
+    Span::current().record("status", status.as_u16());
+}
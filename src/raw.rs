@@ -1,16 +1,18 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{NotificationKey, NotificationKeyName, RegistrationToken};
+
 /// Represents a POST operation to fcm. See <https://firebase.google.com/docs/cloud-messaging/android/device-group>
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(tag = "operation", rename_all = "lowercase")]
 pub enum Operation {
     /// Create a new device group with the following name
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#creating_a_device_group>
     Create {
         /// Name of the dev device group
-        notification_key_name: String,
+        notification_key_name: NotificationKeyName,
         /// registration IDS to create the device group with
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
     /// Add a devices to the device group
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#adding_and_removing_devices_from_a_device_group>
@@ -19,11 +21,11 @@ pub enum Operation {
         /// notification_key_name is not required for adding/removing registration tokens, but including it protects you against
         /// accidentally using the incorrect notification_key.
         #[serde(skip_serializing_if = "Option::is_none")]
-        notification_key_name: Option<String>,
+        notification_key_name: Option<NotificationKeyName>,
         /// Device group notification key
-        notification_key: String,
+        notification_key: NotificationKey,
         /// Registration IDS to add
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
     /// Remove a device from a device group
     /// See <https://firebase.google.com/docs/cloud-messaging/android/device-group#adding_and_removing_devices_from_a_device_group>
@@ -32,17 +34,69 @@ pub enum Operation {
         /// notification_key_name is not required for adding/removing registration tokens, but including it protects you against
         /// accidentally using the incorrect notification_key.
         #[serde(skip_serializing_if = "Option::is_none")]
-        notification_key_name: Option<String>,
+        notification_key_name: Option<NotificationKeyName>,
         /// Device group notification key
-        notification_key: String,
+        notification_key: NotificationKey,
         /// Registration IDS to add
-        registration_ids: Vec<String>,
+        registration_ids: Vec<RegistrationToken>,
     },
 }
 
+impl Operation {
+    /// Whether repeating the operation has the same effect as applying it once.
+    ///
+    /// Removing is not: FCM deletes a group once its last member is removed, so repeating a removal that
+    /// already succeeded fails with `no valid registration ids` or `notification_key not found`.
+    pub(crate) fn is_idempotent(&self) -> bool {
+        matches!(self, Operation::Add { .. })
+    }
+
+    /// Lowercase name of the operation, as sent to FCM
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Operation::Create { .. } => "create",
+            Operation::Add { .. } => "add",
+            Operation::Remove { .. } => "remove",
+        }
+    }
+
+    /// Registration ids the operation applies to
+    pub(crate) fn registration_ids(&self) -> &[RegistrationToken] {
+        match self {
+            Operation::Create {
+                registration_ids, ..
+            }
+            | Operation::Add {
+                registration_ids, ..
+            }
+            | Operation::Remove {
+                registration_ids, ..
+            } => registration_ids,
+        }
+    }
+
+    /// Name of the group the operation applies to, if it is known
+    pub(crate) fn notification_key_name(&self) -> Option<&NotificationKeyName> {
+        match self {
+            Operation::Create {
+                notification_key_name,
+                ..
+            } => Some(notification_key_name),
+            Operation::Add {
+                notification_key_name,
+                ..
+            }
+            | Operation::Remove {
+                notification_key_name,
+                ..
+            } => notification_key_name.as_ref(),
+        }
+    }
+}
+
 /// Response from a POST Operation
-#[derive(Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct OperationResponse {
     /// Key of the effected device group
-    pub notification_key: String,
+    pub notification_key: NotificationKey,
 }
@@ -0,0 +1,142 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    FCMDeviceGroup, FCMDeviceGroupClient, NotificationKeyName, RegistrationToken,
+    error::{FCMDeviceGroupsRequestError, ReconcileError, operation_errors::GetKeyError},
+    sharding::MAX_GROUP_MEMBERS,
+};
+
+/// What [`FCMDeviceGroupClient::reconcile`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// The group after reconciling, `None` if it does not exist because no registration ids were desired
+    pub group: Option<FCMDeviceGroup>,
+    /// Whether the group had to be created
+    pub created: bool,
+    /// Registration ids that were added
+    pub added: Vec<RegistrationToken>,
+    /// Registration ids that were removed
+    pub removed: Vec<RegistrationToken>,
+    /// Whether the members before reconciling were known.
+    ///
+    /// They are unknown when the group already existed but the client has no registry, or the registry has no
+    /// record of the group. Every desired id is then sent as an addition, and members that are not desired
+    /// can't be found and are left in the group.
+    pub members_known: bool,
+}
+
+impl ReconcileReport {
+    /// Whether the group was already in the desired state
+    pub fn is_unchanged(&self) -> bool {
+        !self.created && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl FCMDeviceGroupClient {
+    /// Make the group's members match `desired_ids`, creating the group if needed.
+    ///
+    /// Current membership is taken from the client's [registry](crate::registry). Without a registry or a record
+    /// of the group the members are unknown, so every desired id is added, nothing is removed and the report's
+    /// [`members_known`](ReconcileReport::members_known) is `false`.
+    ///
+    /// Changes are ordered so the group never holds more than [`MAX_GROUP_MEMBERS`]: ids are added while there is
+    /// room, then removed, then the rest are added. At least one member is kept until the last additions, so the
+    /// group is never emptied (and deleted by FCM) on the way to a non-empty desired state.
+    ///
+    /// If another writer creates the group between looking it up and creating it, the desired ids are added to
+    /// their group as with [`ensure_group`](Self::ensure_group).
+    pub async fn reconcile(
+        &self,
+        notification_key_name: NotificationKeyName,
+        desired_ids: impl IntoIterator<Item = RegistrationToken>,
+    ) -> Result<ReconcileReport, ReconcileError> {
+        let desired: BTreeSet<RegistrationToken> = desired_ids.into_iter().collect();
+
+        let group = match self.get_key(notification_key_name.clone()).await {
+            Ok(group) => group,
+            Err(FCMDeviceGroupsRequestError::BadRequestError(GetKeyError::KeyNotFound)) => {
+                if desired.is_empty() {
+                    return Ok(ReconcileReport {
+                        members_known: true,
+                        ..Default::default()
+                    });
+                }
+                let added: Vec<RegistrationToken> = desired.into_iter().collect();
+                let ensured = self
+                    .ensure_group(notification_key_name.clone(), added.clone())
+                    .await?;
+                if !ensured.created {
+                    log::warn!(
+                        "Group {notification_key_name} was created concurrently, only adding the desired registration ids"
+                    );
+                }
+                return Ok(ReconcileReport {
+                    group: Some(ensured.group),
+                    created: ensured.created,
+                    added,
+                    removed: Vec::new(),
+                    members_known: ensured.created,
+                });
+            }
+            Err(e) => return Err(ReconcileError::GetKey(e)),
+        };
+
+        let known = match self.registry() {
+            Some(registry) => registry
+                .group(notification_key_name.as_str())?
+                .map(|record| record.registration_ids),
+            None => None,
+        };
+        let members_known = known.is_some();
+        if !members_known {
+            log::warn!(
+                "Members of group {notification_key_name} are unknown, only adding the desired registration ids"
+            );
+        }
+        let known = known.unwrap_or_default();
+        let added: Vec<RegistrationToken> = desired.difference(&known).cloned().collect();
+        let removed: Vec<RegistrationToken> = known.difference(&desired).cloned().collect();
+
+        let mut group = group;
+        let mut members = known.len();
+        let mut to_add = added.as_slice();
+        let mut to_remove = removed.as_slice();
+        while !to_add.is_empty() || !to_remove.is_empty() {
+            let room = MAX_GROUP_MEMBERS.saturating_sub(members);
+            // With no room and nothing left to remove the desired ids can't fit, let FCM reject them
+            if !to_add.is_empty() && (room > 0 || to_remove.is_empty()) {
+                let (batch, rest) = to_add.split_at(room.clamp(1, to_add.len()));
+                group = self
+                    .add_to_group(group, batch.to_vec())
+                    .await
+                    .map_err(ReconcileError::ChangeMembers)?;
+                members += batch.len();
+                to_add = rest;
+            } else {
+                // Keep a member while there is more to add
+                let count = if to_add.is_empty() {
+                    to_remove.len()
+                } else {
+                    to_remove.len().min(members - 1)
+                };
+                let (batch, rest) = to_remove.split_at(count);
+                group = self
+                    .remove_from_group(group, batch.to_vec())
+                    .await
+                    .map_err(ReconcileError::ChangeMembers)?;
+                members -= batch.len();
+                to_remove = rest;
+            }
+        }
+        // FCM deletes the group once its last member is removed
+        let deleted = desired.is_empty() && !removed.is_empty();
+
+        Ok(ReconcileReport {
+            group: (!deleted).then_some(group),
+            created: false,
+            added,
+            removed,
+            members_known,
+        })
+    }
+}
@@ -0,0 +1,317 @@
+//! Local record of device group membership.
+//!
+//! FCM has no API to list the members of a device group, so a [`GroupRegistry`] attached to an
+//! [`FCMDeviceGroupClient`](crate::FCMDeviceGroupClient) with
+//! [`with_registry`](crate::FCMDeviceGroupClient::with_registry) keeps track of every successful
+//! [`create_group`](crate::FCMDeviceGroupClient::create_group),
+//! [`add_to_group`](crate::FCMDeviceGroupClient::add_to_group) and
+//! [`remove_from_group`](crate::FCMDeviceGroupClient::remove_from_group) call.
+//!
+//! The [`blocking`](crate::blocking) client records changes the same way.
+//!
+//! Only changes made through the client are recorded, so the registry can drift from FCM if groups are also
+//! changed elsewhere.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    FCMDeviceGroup, NotificationKey, NotificationKeyName, Operation, OperationResponse,
+    RegistrationToken,
+    error::{self, FCMDeviceGroupError, operation_errors::OperationResult},
+};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::{OperationRecord, SqliteRegistry};
+
+/// Kind of change made to a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MembershipChange {
+    /// The group was created with the registration ids
+    Create,
+    /// The registration ids were added to the group
+    Add,
+    /// The registration ids were removed from the group
+    Remove,
+}
+
+/// Membership of a device group as known locally
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupRecord {
+    /// Name of the device group
+    pub notification_key_name: NotificationKeyName,
+    /// Every notification key returned for this group, oldest first
+    pub notification_keys: Vec<NotificationKey>,
+    /// Registration ids currently in the group
+    pub registration_ids: BTreeSet<RegistrationToken>,
+}
+
+impl GroupRecord {
+    /// An empty record for the given group
+    pub fn new(notification_key_name: NotificationKeyName) -> Self {
+        Self {
+            notification_key_name,
+            notification_keys: Vec::new(),
+            registration_ids: BTreeSet::new(),
+        }
+    }
+
+    /// Update the record with a change made to the group
+    pub fn apply(
+        &mut self,
+        group: &FCMDeviceGroup,
+        change: MembershipChange,
+        registration_ids: &[RegistrationToken],
+    ) {
+        if !self.notification_keys.contains(&group.notification_key) {
+            self.notification_keys.push(group.notification_key.clone());
+        }
+        match change {
+            MembershipChange::Create | MembershipChange::Add => self
+                .registration_ids
+                .extend(registration_ids.iter().cloned()),
+            MembershipChange::Remove => {
+                for id in registration_ids {
+                    self.registration_ids.remove(id);
+                }
+            }
+        }
+    }
+
+    /// The most recently returned notification key
+    pub fn notification_key(&self) -> Option<&NotificationKey> {
+        self.notification_keys.last()
+    }
+}
+
+/// Error reading or writing a registry
+#[derive(Debug, Error)]
+pub enum RegistryError {
+    #[allow(missing_docs)]
+    #[error("Registry IO Error")]
+    Io(#[from] io::Error),
+    #[allow(missing_docs)]
+    #[error("Registry Serialization Error")]
+    Json(#[from] serde_json::Error),
+    #[allow(missing_docs)]
+    #[cfg(feature = "sqlite")]
+    #[error("Registry Database Error")]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// Storage for device group membership
+pub trait GroupRegistry: Send + Sync {
+    /// Record a successful change to a group.
+    ///
+    /// FCM deletes a group once its last member is removed, so implementations should forget groups left
+    /// without members.
+    fn record(
+        &self,
+        group: &FCMDeviceGroup,
+        change: MembershipChange,
+        registration_ids: &[RegistrationToken],
+    ) -> Result<(), RegistryError>;
+
+    /// Record an operation sent to FCM along with the response or a description of the error.
+    ///
+    /// Called for every operation, including failed ones and ones applied with
+    /// [`FCMDeviceGroupClient::apply`](crate::FCMDeviceGroupClient::apply). Does nothing by default.
+    fn record_operation(
+        &self,
+        operation: &Operation,
+        outcome: Result<&OperationResponse, &str>,
+    ) -> Result<(), RegistryError> {
+        let _ = (operation, outcome);
+        Ok(())
+    }
+
+    /// Look up a group by name
+    fn group(&self, notification_key_name: &str) -> Result<Option<GroupRecord>, RegistryError>;
+
+    /// Every known group
+    fn groups(&self) -> Result<Vec<GroupRecord>, RegistryError>;
+}
+
+/// Record an operation and its outcome in the registry's history, if there is one
+pub(crate) fn record_operation<E: FCMDeviceGroupError + 'static>(
+    registry: Option<&dyn GroupRegistry>,
+    operation: &Operation,
+    response: &OperationResult<OperationResponse, E>,
+) {
+    let Some(registry) = registry else {
+        return;
+    };
+    let result = match response {
+        Ok(response) => registry.record_operation(operation, Ok(response)),
+        Err(e) => registry.record_operation(operation, Err(&error::describe(e))),
+    };
+    if let Err(e) = result {
+        log::warn!("Failed to record operation in registry: {e}");
+    }
+}
+
+/// Record a successful operation in the registry, if there is one.
+///
+/// The change has already been applied by FCM, so failing to record it is logged rather than returned.
+pub(crate) fn record_change(
+    registry: Option<&dyn GroupRegistry>,
+    group: &FCMDeviceGroup,
+    operation: &Operation,
+) {
+    let Some(registry) = registry else {
+        return;
+    };
+    let (change, registration_ids) = match operation {
+        Operation::Create {
+            registration_ids, ..
+        } => (MembershipChange::Create, registration_ids),
+        Operation::Add {
+            registration_ids, ..
+        } => (MembershipChange::Add, registration_ids),
+        Operation::Remove {
+            registration_ids, ..
+        } => (MembershipChange::Remove, registration_ids),
+    };
+    if let Err(e) = registry.record(group, change, registration_ids) {
+        log::warn!(
+            "Failed to record change to group {} in registry: {e}",
+            group.notification_key_name
+        );
+    }
+}
+
+/// Apply a change to a map of records, dropping the group if it has no members left
+fn apply_change(
+    groups: &mut BTreeMap<NotificationKeyName, GroupRecord>,
+    group: &FCMDeviceGroup,
+    change: MembershipChange,
+    registration_ids: &[RegistrationToken],
+) {
+    let record = groups
+        .entry(group.notification_key_name.clone())
+        .or_insert_with(|| GroupRecord::new(group.notification_key_name.clone()));
+    record.apply(group, change, registration_ids);
+    if record.registration_ids.is_empty() {
+        groups.remove(&group.notification_key_name);
+    }
+}
+
+/// Registry kept in memory, lost when the process exits
+#[derive(Debug, Default)]
+pub struct InMemoryRegistry {
+    groups: Mutex<BTreeMap<NotificationKeyName, GroupRecord>>,
+}
+
+impl InMemoryRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl GroupRegistry for InMemoryRegistry {
+    fn record(
+        &self,
+        group: &FCMDeviceGroup,
+        change: MembershipChange,
+        registration_ids: &[RegistrationToken],
+    ) -> Result<(), RegistryError> {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        apply_change(&mut groups, group, change, registration_ids);
+        Ok(())
+    }
+
+    fn group(&self, notification_key_name: &str) -> Result<Option<GroupRecord>, RegistryError> {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(groups.get(notification_key_name).cloned())
+    }
+
+    fn groups(&self) -> Result<Vec<GroupRecord>, RegistryError> {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(groups.values().cloned().collect())
+    }
+}
+
+/// Registry stored as a JSON file, rewritten after every change
+#[derive(Debug)]
+pub struct JsonFileRegistry {
+    path: PathBuf,
+    groups: Mutex<BTreeMap<NotificationKeyName, GroupRecord>>,
+}
+
+impl JsonFileRegistry {
+    /// Open the registry at `path`, starting empty if the file does not exist
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, RegistryError> {
+        let path = path.into();
+        let groups = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice::<Vec<GroupRecord>>(&contents)?
+                .into_iter()
+                .map(|record| (record.notification_key_name.clone(), record))
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path,
+            groups: Mutex::new(groups),
+        })
+    }
+
+    /// Path of the backing file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write the records to a temporary file and move it into place so the file is never half written
+    fn save(
+        &self,
+        groups: &BTreeMap<NotificationKeyName, GroupRecord>,
+    ) -> Result<(), RegistryError> {
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let mut file = fs::File::create(&temp_path)?;
+        serde_json::to_writer_pretty(&mut file, &groups.values().collect::<Vec<_>>())?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&temp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl GroupRegistry for JsonFileRegistry {
+    fn record(
+        &self,
+        group: &FCMDeviceGroup,
+        change: MembershipChange,
+        registration_ids: &[RegistrationToken],
+    ) -> Result<(), RegistryError> {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        // Only keep the change once it is saved, so memory and file don't disagree if saving fails
+        let mut updated = groups.clone();
+        apply_change(&mut updated, group, change, registration_ids);
+        self.save(&updated)?;
+        *groups = updated;
+        Ok(())
+    }
+
+    fn group(&self, notification_key_name: &str) -> Result<Option<GroupRecord>, RegistryError> {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(groups.get(notification_key_name).cloned())
+    }
+
+    fn groups(&self) -> Result<Vec<GroupRecord>, RegistryError> {
+        let groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(groups.values().cloned().collect())
+    }
+}
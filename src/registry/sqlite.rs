@@ -0,0 +1,312 @@
+//! SQLite backed registry.
+//!
+//! Besides group membership, [`SqliteRegistry`] keeps a history of every operation applied to FCM. The schema is
+//! stable so the database can be read by other tools:
+//!
+//! - `device_groups(notification_key_name, created_at, updated_at)`
+//! - `notification_keys(notification_key, notification_key_name, created_at)`
+//! - `group_members(notification_key_name, registration_id, added_at)`
+//! - `operations(id, operation, notification_key_name, notification_key, request, response_key, error, applied_at)`
+//!
+//! Timestamps are seconds since the unix epoch and `request` is the operation as sent to FCM in JSON.
+use std::{
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::{GroupRecord, GroupRegistry, MembershipChange, RegistryError};
+use crate::{FCMDeviceGroup, NotificationKey, Operation, OperationResponse, RegistrationToken};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS device_groups (
+    notification_key_name TEXT PRIMARY KEY NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS notification_keys (
+    notification_key TEXT PRIMARY KEY NOT NULL,
+    notification_key_name TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS group_members (
+    notification_key_name TEXT NOT NULL,
+    registration_id TEXT NOT NULL,
+    added_at INTEGER NOT NULL,
+    PRIMARY KEY (notification_key_name, registration_id)
+);
+CREATE TABLE IF NOT EXISTS operations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    operation TEXT NOT NULL,
+    notification_key_name TEXT,
+    notification_key TEXT,
+    request TEXT NOT NULL,
+    response_key TEXT,
+    error TEXT,
+    applied_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS operations_by_name ON operations (notification_key_name);
+";
+
+/// An operation applied to FCM and its outcome
+#[derive(Debug)]
+pub struct OperationRecord {
+    /// Sequence number of the operation
+    pub id: i64,
+    /// The operation sent to FCM
+    pub operation: Operation,
+    /// The response, if the operation succeeded
+    pub response: Option<OperationResponse>,
+    /// Description of the error, if the operation failed
+    pub error: Option<String>,
+    /// When the operation completed
+    pub applied_at: SystemTime,
+}
+
+/// Registry stored in a SQLite database
+#[derive(Debug)]
+pub struct SqliteRegistry {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteRegistry {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, RegistryError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Create a database that only lives in memory
+    pub fn open_in_memory() -> Result<Self, RegistryError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    /// Use an existing connection, creating the tables if needed
+    pub fn with_connection(connection: Connection) -> Result<Self, RegistryError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Every recorded operation, oldest first
+    pub fn operations(&self) -> Result<Vec<OperationRecord>, RegistryError> {
+        self.query_operations(
+            "SELECT id, request, response_key, error, applied_at FROM operations ORDER BY id",
+            [],
+        )
+    }
+
+    /// Operations recorded for a group, oldest first
+    pub fn group_operations(
+        &self,
+        notification_key_name: &str,
+    ) -> Result<Vec<OperationRecord>, RegistryError> {
+        self.query_operations(
+            "SELECT id, request, response_key, error, applied_at FROM operations
+            WHERE notification_key_name = ?1 ORDER BY id",
+            [notification_key_name],
+        )
+    }
+
+    fn query_operations(
+        &self,
+        sql: &str,
+        params: impl rusqlite::Params,
+    ) -> Result<Vec<OperationRecord>, RegistryError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map(params, |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<NotificationKey>>(2)?,
+                row.get::<_, Option<String>>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (id, request, response_key, error, applied_at) = row?;
+            Ok(OperationRecord {
+                id,
+                operation: serde_json::from_str(&request)?,
+                response: response_key
+                    .map(|notification_key| OperationResponse { notification_key }),
+                error,
+                applied_at: UNIX_EPOCH + Duration::from_secs(applied_at.max(0) as u64),
+            })
+        })
+        .collect()
+    }
+
+    fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl GroupRegistry for SqliteRegistry {
+    fn record(
+        &self,
+        group: &FCMDeviceGroup,
+        change: MembershipChange,
+        registration_ids: &[RegistrationToken],
+    ) -> Result<(), RegistryError> {
+        let now = now();
+        let name = group.notification_key_name.as_str();
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+
+        transaction.execute(
+            "INSERT INTO device_groups (notification_key_name, created_at, updated_at) VALUES (?1, ?2, ?2)
+            ON CONFLICT (notification_key_name) DO UPDATE SET updated_at = excluded.updated_at",
+            params![name, now],
+        )?;
+        transaction.execute(
+            "INSERT OR IGNORE INTO notification_keys (notification_key, notification_key_name, created_at)
+            VALUES (?1, ?2, ?3)",
+            params![group.notification_key, name, now],
+        )?;
+        match change {
+            MembershipChange::Create | MembershipChange::Add => {
+                let mut insert = transaction.prepare(
+                    "INSERT OR IGNORE INTO group_members (notification_key_name, registration_id, added_at)
+                    VALUES (?1, ?2, ?3)",
+                )?;
+                for id in registration_ids {
+                    insert.execute(params![name, id, now])?;
+                }
+            }
+            MembershipChange::Remove => {
+                let mut delete = transaction.prepare(
+                    "DELETE FROM group_members WHERE notification_key_name = ?1 AND registration_id = ?2",
+                )?;
+                for id in registration_ids {
+                    delete.execute(params![name, id])?;
+                }
+            }
+        }
+
+        // FCM deletes groups without members
+        let members: i64 = transaction.query_row(
+            "SELECT COUNT(*) FROM group_members WHERE notification_key_name = ?1",
+            [name],
+            |row| row.get(0),
+        )?;
+        if members == 0 {
+            transaction.execute(
+                "DELETE FROM notification_keys WHERE notification_key_name = ?1",
+                [name],
+            )?;
+            transaction.execute(
+                "DELETE FROM device_groups WHERE notification_key_name = ?1",
+                [name],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn record_operation(
+        &self,
+        operation: &Operation,
+        outcome: Result<&OperationResponse, &str>,
+    ) -> Result<(), RegistryError> {
+        let (kind, name, key) = match operation {
+            Operation::Create {
+                notification_key_name,
+                ..
+            } => ("create", Some(notification_key_name), None),
+            Operation::Add {
+                notification_key_name,
+                notification_key,
+                ..
+            } => (
+                "add",
+                notification_key_name.as_ref(),
+                Some(notification_key),
+            ),
+            Operation::Remove {
+                notification_key_name,
+                notification_key,
+                ..
+            } => (
+                "remove",
+                notification_key_name.as_ref(),
+                Some(notification_key),
+            ),
+        };
+        let (response_key, error) = match outcome {
+            Ok(response) => (Some(response.notification_key.as_str()), None),
+            Err(error) => (None, Some(error)),
+        };
+        self.connection().execute(
+            "INSERT INTO operations
+            (operation, notification_key_name, notification_key, request, response_key, error, applied_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                kind,
+                name,
+                key,
+                serde_json::to_string(operation)?,
+                response_key,
+                error,
+                now()
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn group(&self, notification_key_name: &str) -> Result<Option<GroupRecord>, RegistryError> {
+        let connection = self.connection();
+        let name = connection
+            .query_row(
+                "SELECT notification_key_name FROM device_groups WHERE notification_key_name = ?1",
+                [notification_key_name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(name) = name else {
+            return Ok(None);
+        };
+
+        let mut record = GroupRecord::new(name);
+        let mut keys = connection.prepare(
+            "SELECT notification_key FROM notification_keys WHERE notification_key_name = ?1 ORDER BY rowid",
+        )?;
+        for key in keys.query_map([notification_key_name], |row| row.get(0))? {
+            record.notification_keys.push(key?);
+        }
+        let mut members = connection.prepare(
+            "SELECT registration_id FROM group_members WHERE notification_key_name = ?1",
+        )?;
+        for id in members.query_map([notification_key_name], |row| row.get(0))? {
+            record.registration_ids.insert(id?);
+        }
+        Ok(Some(record))
+    }
+
+    fn groups(&self) -> Result<Vec<GroupRecord>, RegistryError> {
+        let names = {
+            let connection = self.connection();
+            let mut statement = connection.prepare(
+                "SELECT notification_key_name FROM device_groups ORDER BY notification_key_name",
+            )?;
+            statement
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<_>, _>>()?
+        };
+        names
+            .iter()
+            .filter_map(|name| self.group(name).transpose())
+            .collect()
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
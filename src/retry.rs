@@ -0,0 +1,185 @@
+//! Retry configuration for requests made to FCM
+use std::{
+    hash::{BuildHasher, RandomState},
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{
+    StatusCode,
+    header::{self, HeaderMap, HeaderValue},
+};
+
+use crate::{
+    error::{FCMDeviceGroupError, RawError, operation_errors::OperationResult},
+    observe,
+};
+
+/// Controls how a failed request is retried.
+///
+/// Only failures that are safe to repeat are retried: `429 Too Many Requests` and `503 Service Unavailable`
+/// responses, and connection failures where the request never reached FCM. Other server errors and timeouts are
+/// only retried for operations that are idempotent, which excludes [`Operation::Create`](crate::Operation::Create)
+/// and [`Operation::Remove`](crate::Operation::Remove).
+///
+/// When a response carries a `Retry-After` header it is used instead of the computed backoff, capped at the
+/// maximum backoff.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+}
+
+impl RetryPolicy {
+    /// A policy that makes at most `max_attempts` attempts, including the first one.
+    ///
+    /// Backoff starts at 500ms and doubles on each attempt up to 32s, with jitter enabled.
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts: max_attempts.max(1),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(32),
+            jitter: true,
+        }
+    }
+
+    /// A policy that never retries
+    pub fn disabled() -> Self {
+        Self::new(1)
+    }
+
+    /// Set the delay before the first retry
+    pub fn with_initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Set the upper bound on the computed backoff and on delays requested with `Retry-After`
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Enable or disable randomizing the backoff
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Maximum number of attempts, including the first one
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay to wait after the given (1 based) attempt failed
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        if self.jitter {
+            // Equal jitter: keep half the backoff and randomize the other half
+            let half = backoff / 2;
+            let random = RandomState::new().hash_one(attempt) as f64 / u64::MAX as f64;
+            half + half.mul_f64(random)
+        } else {
+            backoff
+        }
+    }
+
+    /// Delay to wait before retrying a response with the given headers, preferring its `Retry-After` header
+    fn response_delay(&self, headers: &HeaderMap, attempt: u32) -> Duration {
+        match headers.get(header::RETRY_AFTER).and_then(parse_retry_after) {
+            Some(retry_after) => retry_after.min(self.max_backoff),
+            None => self.backoff(attempt),
+        }
+    }
+
+    /// Decide what to do after the given (1 based) attempt of a request.
+    ///
+    /// `outcome` is the status and headers of the response, or the error if there was none. Shared by the async
+    /// and blocking clients, which only differ in how they send requests and wait.
+    pub(crate) fn decide(
+        &self,
+        outcome: Result<(StatusCode, &HeaderMap), &RawError>,
+        attempt: u32,
+        idempotent: bool,
+    ) -> Decision {
+        let (delay, error) = match outcome {
+            Ok((status, headers)) => {
+                observe::record_status(status);
+                if !is_retryable_status(status, idempotent) {
+                    return Decision::Return;
+                }
+                (self.response_delay(headers, attempt), status.to_string())
+            }
+            Err(RawError::HttpError(e)) if is_retryable_error(e, idempotent) => {
+                (self.backoff(attempt), e.to_string())
+            }
+            Err(_) => return Decision::Return,
+        };
+        if attempt >= self.max_attempts {
+            return Decision::GiveUp;
+        }
+        log::warn!(
+            "FCM request attempt {attempt}/{} failed with {error}, retrying in {delay:?}",
+            self.max_attempts
+        );
+        Decision::Retry(delay)
+    }
+}
+
+/// What to do after an attempt of a request, see [`RetryPolicy::decide`]
+pub(crate) enum Decision {
+    /// Return the outcome of the attempt
+    Return,
+    /// Return the outcome of the attempt, which was the last one allowed
+    GiveUp,
+    /// Try again after the delay
+    Retry(Duration),
+}
+
+impl Decision {
+    /// Finish with the outcome of the attempt, wrapping errors in
+    /// [`RetriesExhausted`](crate::error::FCMDeviceGroupsRequestError::RetriesExhausted) if retrying gave up
+    pub(crate) fn finish<T, E: FCMDeviceGroupError>(
+        self,
+        result: OperationResult<T, E>,
+        attempt: u32,
+    ) -> OperationResult<T, E> {
+        match self {
+            Decision::GiveUp => result.map_err(|e| e.with_attempts(attempt)),
+            Decision::Return | Decision::Retry(_) => result,
+        }
+    }
+}
+
+/// Whether a response with the given status can be retried
+fn is_retryable_status(status: StatusCode, idempotent: bool) -> bool {
+    match status {
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => true,
+        StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::GATEWAY_TIMEOUT => idempotent,
+        _ => false,
+    }
+}
+
+/// Whether a request that failed before getting a response can be retried
+fn is_retryable_error(error: &reqwest::Error, idempotent: bool) -> bool {
+    error.is_connect() || (idempotent && error.is_timeout())
+}
+
+fn parse_retry_after(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
@@ -0,0 +1,270 @@
+//! Logical device groups larger than FCM allows.
+//!
+//! FCM caps how many registration ids one notification key can hold. A [`ShardedGroup`] spreads its members
+//! over FCM groups named `name#0`, `name#1`, ... and the client methods below route each change to the right
+//! shard.
+//!
+//! FCM can't list group members, so the sharded group keeps track of which member lives in which shard. It is
+//! serializable so that state can be stored between runs.
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Deserializer, Serialize, de};
+
+use crate::{
+    FCMDeviceGroup, FCMDeviceGroupClient, NotificationKey, NotificationKeyName, RegistrationToken,
+    error::{
+        EnsureGroupError, FCMDeviceGroupError, FCMDeviceGroupsBadRequest,
+        FCMDeviceGroupsRequestError, InvalidIdentifierError, PartialBatchFailure,
+        ShardedGroupError, operation_errors::OperationResult,
+    },
+    message::{GroupSendResponse, Message},
+};
+
+/// Maximum number of registration ids FCM allows in a single device group
+pub const MAX_GROUP_MEMBERS: usize = 20;
+
+/// A logical group whose members are spread across several FCM device groups
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShardedGroup {
+    name: NotificationKeyName,
+    #[serde(deserialize_with = "deserialize_shard_size")]
+    shard_size: usize,
+    shards: Vec<Shard>,
+}
+
+/// One FCM device group backing a [`ShardedGroup`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    /// Name of the FCM device group
+    pub notification_key_name: NotificationKeyName,
+    /// Key of the FCM device group, `None` while the shard has no members and so does not exist in FCM
+    pub notification_key: Option<NotificationKey>,
+    /// Registration ids in this shard
+    pub registration_ids: BTreeSet<RegistrationToken>,
+}
+
+impl Shard {
+    fn group(&self) -> Option<FCMDeviceGroup> {
+        Some(FCMDeviceGroup {
+            notification_key_name: self.notification_key_name.clone(),
+            notification_key: self.notification_key.clone()?,
+        })
+    }
+
+    /// Record the batches added before a request split into batches failed
+    fn added_before(&mut self, failure: &PartialBatchFailure) {
+        self.notification_key = Some(failure.group.notification_key.clone());
+        self.registration_ids
+            .extend(failure.applied.iter().flatten().cloned());
+    }
+
+    /// Record the batches removed before a request split into batches failed
+    fn removed_before(&mut self, failure: &PartialBatchFailure) {
+        for id in failure.applied.iter().flatten() {
+            self.registration_ids.remove(id);
+        }
+        self.notification_key =
+            (!self.registration_ids.is_empty()).then(|| failure.group.notification_key.clone());
+    }
+}
+
+/// Reject stored shard sizes [`ShardedGroup::with_shard_size`] would not allow
+fn deserialize_shard_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    let shard_size = usize::deserialize(deserializer)?;
+    if (1..=MAX_GROUP_MEMBERS).contains(&shard_size) {
+        Ok(shard_size)
+    } else {
+        Err(de::Error::invalid_value(
+            de::Unexpected::Unsigned(shard_size as u64),
+            &"a shard size from 1 to MAX_GROUP_MEMBERS",
+        ))
+    }
+}
+
+/// The batches applied before a call failed, if it failed part way through
+fn partial_failure<E: FCMDeviceGroupError>(
+    error: &FCMDeviceGroupsRequestError<E>,
+) -> Option<&PartialBatchFailure> {
+    match error {
+        FCMDeviceGroupsRequestError::PartialBatchFailure(failure) => Some(failure),
+        _ => None,
+    }
+}
+
+impl ShardedGroup {
+    /// An empty sharded group with shards of [`MAX_GROUP_MEMBERS`]
+    pub fn new(name: NotificationKeyName) -> Self {
+        Self {
+            name,
+            shard_size: MAX_GROUP_MEMBERS,
+            shards: Vec::new(),
+        }
+    }
+
+    /// Set the maximum number of members per shard, between 1 and [`MAX_GROUP_MEMBERS`]
+    pub fn with_shard_size(mut self, shard_size: usize) -> Self {
+        self.shard_size = shard_size.clamp(1, MAX_GROUP_MEMBERS);
+        self
+    }
+
+    /// Name of the logical group
+    pub fn name(&self) -> &NotificationKeyName {
+        &self.name
+    }
+
+    /// Name of the FCM device group backing the shard at `index`.
+    ///
+    /// Fails if the suffix makes the name too long.
+    pub fn shard_name(&self, index: usize) -> Result<NotificationKeyName, InvalidIdentifierError> {
+        NotificationKeyName::new(format!("{}#{index}", self.name))
+    }
+
+    /// All shards, including empty ones
+    pub fn shards(&self) -> &[Shard] {
+        &self.shards
+    }
+
+    /// Notification keys of every shard that exists in FCM
+    pub fn notification_keys(&self) -> impl Iterator<Item = &NotificationKey> {
+        self.shards
+            .iter()
+            .filter_map(|shard| shard.notification_key.as_ref())
+    }
+
+    /// Every member of the group
+    pub fn registration_ids(&self) -> impl Iterator<Item = &RegistrationToken> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.registration_ids.iter())
+    }
+
+    /// Whether the registration id is a member of any shard
+    pub fn contains(&self, registration_id: &str) -> bool {
+        self.shards
+            .iter()
+            .any(|shard| shard.registration_ids.contains(registration_id))
+    }
+}
+
+impl FCMDeviceGroupClient {
+    /// Add registration ids to a sharded group, filling existing shards before creating new ones.
+    ///
+    /// Ids that are already members are skipped. If a request fails, `group` still reflects every change that
+    /// was applied.
+    pub async fn add_to_sharded_group(
+        &self,
+        group: &mut ShardedGroup,
+        registration_ids: impl IntoIterator<Item = RegistrationToken>,
+    ) -> Result<(), ShardedGroupError> {
+        let mut pending: Vec<RegistrationToken> = registration_ids
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .filter(|id| !group.contains(id.as_str()))
+            .collect();
+
+        let mut index = 0;
+        while !pending.is_empty() {
+            if index == group.shards.len() {
+                group.shards.push(Shard {
+                    notification_key_name: group.shard_name(index)?,
+                    notification_key: None,
+                    registration_ids: BTreeSet::new(),
+                });
+            }
+            let shard = &mut group.shards[index];
+            index += 1;
+
+            let room = group
+                .shard_size
+                .saturating_sub(shard.registration_ids.len());
+            if room == 0 {
+                continue;
+            }
+            let batch: Vec<RegistrationToken> = pending.drain(..room.min(pending.len())).collect();
+
+            let updated = match shard.group() {
+                Some(existing) => match self.add_to_group(existing, batch.clone()).await {
+                    Ok(updated) => updated,
+                    Err(e) => {
+                        if let Some(failure) = partial_failure(&e) {
+                            shard.added_before(failure);
+                        }
+                        return Err(ShardedGroupError::ChangeMembers(e));
+                    }
+                },
+                None => match self
+                    .ensure_group(shard.notification_key_name.clone(), batch.clone())
+                    .await
+                {
+                    Ok(ensured) => ensured.group,
+                    Err(e) => {
+                        let failure = match &e {
+                            EnsureGroupError::Create(e) => partial_failure(e),
+                            EnsureGroupError::ChangeMembers(e) => partial_failure(e),
+                            _ => None,
+                        };
+                        if let Some(failure) = failure {
+                            shard.added_before(failure);
+                        }
+                        return Err(e.into());
+                    }
+                },
+            };
+            shard.notification_key = Some(updated.notification_key);
+            shard.registration_ids.extend(batch);
+        }
+        Ok(())
+    }
+
+    /// Remove registration ids from whichever shards hold them. Ids that are not members are ignored.
+    ///
+    /// If a request fails, `group` still reflects every change that was applied.
+    pub async fn remove_from_sharded_group(
+        &self,
+        group: &mut ShardedGroup,
+        registration_ids: impl IntoIterator<Item = RegistrationToken>,
+    ) -> Result<(), ShardedGroupError> {
+        let registration_ids: BTreeSet<RegistrationToken> = registration_ids.into_iter().collect();
+        for shard in &mut group.shards {
+            let batch: Vec<RegistrationToken> = shard
+                .registration_ids
+                .intersection(&registration_ids)
+                .cloned()
+                .collect();
+            let Some(existing) = shard.group().filter(|_| !batch.is_empty()) else {
+                continue;
+            };
+
+            let updated = match self.remove_from_group(existing, batch.clone()).await {
+                Ok(updated) => updated,
+                Err(e) => {
+                    if let Some(failure) = partial_failure(&e) {
+                        shard.removed_before(failure);
+                    }
+                    return Err(ShardedGroupError::ChangeMembers(e));
+                }
+            };
+            for id in &batch {
+                shard.registration_ids.remove(id);
+            }
+            // FCM deletes the group once its last member is removed
+            shard.notification_key =
+                (!shard.registration_ids.is_empty()).then_some(updated.notification_key);
+        }
+        Ok(())
+    }
+
+    /// Send a message to every shard of a sharded group, returning the response of each shard in order
+    pub async fn send_to_sharded_group(
+        &self,
+        group: &ShardedGroup,
+        message: &Message,
+    ) -> OperationResult<Vec<GroupSendResponse>, FCMDeviceGroupsBadRequest> {
+        let mut responses = Vec::new();
+        for notification_key in group.notification_keys() {
+            responses.push(self.send_to_key(notification_key, message).await?);
+        }
+        Ok(responses)
+    }
+}
@@ -0,0 +1,190 @@
+//! Topic subscription management through the Instance ID batch API.
+//! See <https://developers.google.com/instance-id/reference/server#manage_relationship_maps_for_multiple_app_instances>
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    FCMDeviceGroupClient, RegistrationToken,
+    error::{TopicBatchError, TopicBatchFailure},
+};
+
+/// Default base URL of the Instance ID API
+pub const IID_URL: &str = "https://iid.googleapis.com/iid/v1";
+
+/// Maximum number of registration tokens the Instance ID API accepts in a single request
+pub const MAX_TOKENS_PER_TOPIC_REQUEST: usize = 1000;
+
+/// Client to subscribe registration tokens to topics.
+///
+/// Created with [`FCMDeviceGroupClient::topics`], sharing its HTTP client, authentication and retry policy.
+#[derive(Clone)]
+pub struct FCMTopicClient {
+    url: Url,
+    client: FCMDeviceGroupClient,
+}
+
+/// Result of subscribing or unsubscribing a single registration token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicResult {
+    /// The registration token
+    pub registration_token: RegistrationToken,
+    /// Why the token could not be updated, if it failed
+    pub error: Option<TopicError>,
+}
+
+/// Per token error returned by the Instance ID API
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum TopicError {
+    /// The registration token is not valid or no longer exists
+    NotFound,
+    /// The registration token or topic name is malformed
+    InvalidArgument,
+    /// The token is subscribed to too many topics
+    TooManyTopics,
+    /// Internal server error, the request can be retried
+    Internal,
+    /// The token belongs to a different project
+    PermissionDenied,
+    /// Any other error
+    Other(String),
+}
+
+impl From<String> for TopicError {
+    fn from(error: String) -> Self {
+        match error.as_str() {
+            "NOT_FOUND" => Self::NotFound,
+            "INVALID_ARGUMENT" => Self::InvalidArgument,
+            "TOO_MANY_TOPICS" => Self::TooManyTopics,
+            "INTERNAL" => Self::Internal,
+            "PERMISSION_DENIED" => Self::PermissionDenied,
+            _ => Self::Other(error),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BatchRequest<'a> {
+    to: &'a str,
+    registration_tokens: &'a [RegistrationToken],
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    results: Vec<BatchResult>,
+}
+
+#[derive(Deserialize)]
+struct BatchResult {
+    error: Option<TopicError>,
+}
+
+impl FCMDeviceGroupClient {
+    /// Create a client for managing topic subscriptions that shares this client's configuration
+    pub fn topics(&self) -> FCMTopicClient {
+        FCMTopicClient {
+            url: Url::parse(IID_URL).expect("The default Instance ID url is valid"),
+            client: self.clone(),
+        }
+    }
+}
+
+impl FCMTopicClient {
+    /// Set the base URL of the Instance ID API. Defaults to [`IID_URL`].
+    pub fn with_url(mut self, url: Url) -> Self {
+        self.url = url;
+        self
+    }
+
+    /// Subscribe registration tokens to a topic
+    ///
+    /// Tokens are sent in batches of [`MAX_TOKENS_PER_TOPIC_REQUEST`]. If a batch fails, the error holds the
+    /// results of the batches before it. Subscribing is idempotent, so the whole call can also be repeated.
+    pub async fn subscribe(
+        &self,
+        topic: &str,
+        registration_tokens: Vec<RegistrationToken>,
+    ) -> Result<Vec<TopicResult>, TopicBatchFailure> {
+        self.batch("batchAdd", topic, registration_tokens).await
+    }
+
+    /// Unsubscribe registration tokens from a topic
+    ///
+    /// Tokens are sent in batches of [`MAX_TOKENS_PER_TOPIC_REQUEST`]. If a batch fails, the error holds the
+    /// results of the batches before it. Unsubscribing is idempotent, so the whole call can also be repeated.
+    pub async fn unsubscribe(
+        &self,
+        topic: &str,
+        registration_tokens: Vec<RegistrationToken>,
+    ) -> Result<Vec<TopicResult>, TopicBatchFailure> {
+        self.batch("batchRemove", topic, registration_tokens).await
+    }
+
+    async fn batch(
+        &self,
+        method: &str,
+        topic: &str,
+        registration_tokens: Vec<RegistrationToken>,
+    ) -> Result<Vec<TopicResult>, TopicBatchFailure> {
+        let url = self.method_url(method);
+        let to = if topic.starts_with("/topics/") {
+            topic.to_owned()
+        } else {
+            format!("/topics/{topic}")
+        };
+
+        let mut results = Vec::with_capacity(registration_tokens.len());
+        let mut batches = registration_tokens.chunks(MAX_TOKENS_PER_TOPIC_REQUEST);
+        while let Some(batch) = batches.next() {
+            match self.send_batch(&url, &to, batch).await {
+                Ok(batch_results) => results.extend(batch_results),
+                Err(source) => {
+                    return Err(TopicBatchFailure {
+                        applied: results,
+                        failed: batch.to_vec(),
+                        remaining: batches.flatten().cloned().collect(),
+                        source,
+                    });
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    async fn send_batch(
+        &self,
+        url: &Url,
+        to: &str,
+        batch: &[RegistrationToken],
+    ) -> Result<Vec<TopicResult>, TopicBatchError> {
+        let request = BatchRequest {
+            to,
+            registration_tokens: batch,
+        };
+        let response: BatchResponse = self
+            .client
+            .execute(|| self.client.client.post(url.clone()).json(&request), true)
+            .await?;
+        if response.results.len() != batch.len() {
+            return Err(TopicBatchError::ResultCountMismatch {
+                expected: batch.len(),
+                actual: response.results.len(),
+            });
+        }
+        Ok(batch
+            .iter()
+            .zip(response.results)
+            .map(|(token, result)| TopicResult {
+                registration_token: token.clone(),
+                error: result.error,
+            })
+            .collect())
+    }
+
+    fn method_url(&self, method: &str) -> Url {
+        let mut url = self.url.clone();
+        let path = format!("{}:{method}", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+        url
+    }
+}
@@ -0,0 +1,222 @@
+//! Validated identifiers used by FCM device groups.
+//!
+//! Notification key names, notification keys and registration tokens are all strings on the wire. Giving each
+//! its own type stops one from being passed where another is expected, and checks each value before it is sent
+//! to FCM.
+//!
+//! The [`Debug`](fmt::Debug) output of [`NotificationKey`] and [`RegistrationToken`] is redacted so they don't end
+//! up in logs, unless revealing them is turned on with [`reveal_secrets_in_debug`]. [`Display`](fmt::Display)
+//! always shows the full value.
+use std::{
+    borrow::Borrow,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::InvalidIdentifierError;
+
+/// Maximum length in bytes of a [`NotificationKeyName`]
+pub const MAX_NOTIFICATION_KEY_NAME_LEN: usize = 256;
+
+/// Maximum length in bytes of a [`NotificationKey`] or [`RegistrationToken`]
+pub const MAX_TOKEN_LEN: usize = 4096;
+
+static REVEAL_SECRETS_IN_DEBUG: AtomicBool = AtomicBool::new(false);
+
+/// Show full notification keys and registration tokens in [`Debug`](fmt::Debug) output.
+///
+/// Off by default. This applies to the whole process, including types that contain keys or tokens such as
+/// [`FCMDeviceGroup`](crate::FCMDeviceGroup) and [`Operation`](crate::Operation).
+pub fn reveal_secrets_in_debug(reveal: bool) {
+    REVEAL_SECRETS_IN_DEBUG.store(reveal, Ordering::Relaxed);
+}
+
+fn fmt_secret(name: &str, value: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if REVEAL_SECRETS_IN_DEBUG.load(Ordering::Relaxed) {
+        f.debug_tuple(name).field(&value).finish()
+    } else {
+        write!(f, "{name}(<redacted>)")
+    }
+}
+
+/// Characters FCM uses in notification keys and registration tokens
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.')
+}
+
+fn validate(
+    kind: &'static str,
+    value: &str,
+    max_len: usize,
+    is_valid_char: fn(char) -> bool,
+) -> Result<(), InvalidIdentifierError> {
+    if value.is_empty() {
+        return Err(InvalidIdentifierError::Empty { kind });
+    }
+    if value.len() > max_len {
+        return Err(InvalidIdentifierError::TooLong { kind, max_len });
+    }
+    match value.chars().find(|&c| !is_valid_char(c)) {
+        Some(character) => Err(InvalidIdentifierError::InvalidCharacter { kind, character }),
+        None => Ok(()),
+    }
+}
+
+macro_rules! identifier {
+    ($(#[$meta:meta])* $name:ident, $kind:literal, $max_len:expr, $is_valid_char:expr, secret) => {
+        identifier!($(#[$meta])* $name, $kind, $max_len, $is_valid_char);
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt_secret(stringify!($name), &self.0, f)
+            }
+        }
+    };
+    ($(#[$meta:meta])* $name:ident, $kind:literal, $max_len:expr, $is_valid_char:expr, public) => {
+        identifier!($(#[$meta])* $name, $kind, $max_len, $is_valid_char);
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_tuple(stringify!($name)).field(&self.0).finish()
+            }
+        }
+    };
+    ($(#[$meta:meta])* $name:ident, $kind:literal, $max_len:expr, $is_valid_char:expr) => {
+        $(#[$meta])*
+        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            /// Validate the value
+            pub fn new(value: impl Into<String>) -> Result<Self, InvalidIdentifierError> {
+                let value = value.into();
+                validate($kind, &value, $max_len, $is_valid_char)?;
+                Ok(Self(value))
+            }
+
+            /// The value as a string
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            /// Take the value as a string
+            pub fn into_string(self) -> String {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = InvalidIdentifierError;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                Self::new(value)
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = InvalidIdentifierError;
+
+            fn try_from(value: String) -> Result<Self, Self::Error> {
+                Self::new(value)
+            }
+        }
+
+        impl TryFrom<&str> for $name {
+            type Error = InvalidIdentifierError;
+
+            fn try_from(value: &str) -> Result<Self, Self::Error> {
+                Self::new(value)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                value.0
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl rusqlite::ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                self.0.to_sql()
+            }
+        }
+
+        #[cfg(feature = "sqlite")]
+        impl rusqlite::types::FromSql for $name {
+            fn column_result(
+                value: rusqlite::types::ValueRef<'_>,
+            ) -> rusqlite::types::FromSqlResult<Self> {
+                let value = String::column_result(value)?;
+                Self::new(value).map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e)))
+            }
+        }
+    };
+}
+
+identifier!(
+    /// Name of a device group, chosen when the group is created.
+    ///
+    /// Must be non-empty, at most [`MAX_NOTIFICATION_KEY_NAME_LEN`] bytes and free of control characters.
+    NotificationKeyName,
+    "notification key name",
+    MAX_NOTIFICATION_KEY_NAME_LEN,
+    |c: char| !c.is_control(),
+    public
+);
+
+identifier!(
+    /// Key FCM returns for a device group, used to send to and change the group.
+    ///
+    /// Must be non-empty, at most [`MAX_TOKEN_LEN`] bytes and made of ASCII letters, digits, `-`, `_`, `:` and `.`.
+    NotificationKey,
+    "notification key",
+    MAX_TOKEN_LEN,
+    is_token_char,
+    secret
+);
+
+identifier!(
+    /// Registration token of a single app instance.
+    ///
+    /// Must be non-empty, at most [`MAX_TOKEN_LEN`] bytes and made of ASCII letters, digits, `-`, `_`, `:` and `.`.
+    RegistrationToken,
+    "registration token",
+    MAX_TOKEN_LEN,
+    is_token_char,
+    secret
+);
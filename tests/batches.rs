@@ -0,0 +1,131 @@
+mod common;
+
+use common::{Reply, StubServer, tokens};
+use fcm_device_group::{
+    FCMDeviceGroup, FCMDeviceGroupClient, MAX_REGISTRATION_IDS_PER_REQUEST, NotificationKeyName,
+    error::FCMDeviceGroupsRequestError, sharding::MAX_GROUP_MEMBERS,
+};
+use serde_json::json;
+
+fn client(server: &StubServer) -> FCMDeviceGroupClient {
+    server.client().with_max_registration_ids_per_request(2)
+}
+
+/// Answer every operation with a key naming how many requests were made so far
+async fn counting_server(fail_at: Option<usize>) -> StubServer {
+    let count = std::sync::Mutex::new(0);
+    StubServer::start(move |_| {
+        let mut count = count.lock().unwrap();
+        *count += 1;
+        if Some(*count) == fail_at {
+            Reply::json(400, json!({"error": "no valid registration ids"}))
+        } else {
+            Reply::json(200, json!({"notification_key": format!("key-{count}")}))
+        }
+    })
+    .await
+}
+
+fn sent_ids(server: &StubServer) -> Vec<(String, Vec<String>)> {
+    server
+        .requests()
+        .iter()
+        .map(|request| {
+            let body = request.json();
+            let ids = body["registration_ids"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|id| id.as_str().unwrap().to_string())
+                .collect();
+            (body["operation"].as_str().unwrap().to_string(), ids)
+        })
+        .collect()
+}
+
+#[test]
+fn default_batch_size_is_the_group_limit() {
+    assert_eq!(MAX_REGISTRATION_IDS_PER_REQUEST, MAX_GROUP_MEMBERS);
+    assert_eq!(MAX_REGISTRATION_IDS_PER_REQUEST, 20);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn create_is_split_into_create_and_adds() {
+    let server = counting_server(None).await;
+    let group = client(&server)
+        .create_group(
+            NotificationKeyName::new("group").unwrap(),
+            tokens(&["a", "b", "c", "d", "e"]),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(group.notification_key, "key-3");
+    assert_eq!(
+        sent_ids(&server),
+        [
+            ("create".to_string(), vec!["a".to_string(), "b".to_string()]),
+            ("add".to_string(), vec!["c".to_string(), "d".to_string()]),
+            ("add".to_string(), vec!["e".to_string()]),
+        ]
+    );
+    assert_eq!(server.requests()[1].json()["notification_key"], "key-1");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn small_changes_are_sent_in_one_request() {
+    let server = counting_server(None).await;
+    let group = FCMDeviceGroup {
+        notification_key_name: NotificationKeyName::new("group").unwrap(),
+        notification_key: "key".parse().unwrap(),
+    };
+    client(&server)
+        .remove_from_group(group, tokens(&["a", "b"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        sent_ids(&server),
+        [("remove".to_string(), vec!["a".to_string(), "b".to_string()])]
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn failed_batch_reports_what_was_applied() {
+    let server = counting_server(Some(2)).await;
+    let mut group = FCMDeviceGroup {
+        notification_key_name: NotificationKeyName::new("group").unwrap(),
+        notification_key: "key".parse().unwrap(),
+    };
+    let error = client(&server)
+        .add_members(&mut group, ["a", "b", "c", "d", "e"])
+        .await
+        .unwrap_err();
+
+    let FCMDeviceGroupsRequestError::PartialBatchFailure(failure) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(failure.group.notification_key, "key-1");
+    assert_eq!(failure.applied, [tokens(&["a", "b"])]);
+    assert_eq!(failure.failed, tokens(&["c", "d"]));
+    assert_eq!(failure.remaining, [tokens(&["e"])]);
+    // The in place handle keeps the key of the last batch that succeeded
+    assert_eq!(group, failure.group);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn failed_first_batch_is_not_a_partial_failure() {
+    let server = counting_server(Some(1)).await;
+    let error = client(&server)
+        .create_group(
+            NotificationKeyName::new("group").unwrap(),
+            tokens(&["a", "b", "c"]),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(_)
+    ));
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,163 @@
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{Reply, SENDER_ID, StubServer, name, tokens};
+use fcm_device_group::{
+    NoToken, RetryPolicy,
+    blocking::FCMDeviceGroupClient,
+    error::{FCMDeviceGroupsRequestError, operation_errors::CreateGroupError},
+    fake_server::FakeFCMServer,
+    registry::InMemoryRegistry,
+};
+use serde_json::json;
+
+/// Start a server on a runtime in a background thread, since the blocking client can't be used from async code
+fn in_background<S: Send + 'static>(start: impl Future<Output = S> + Send + 'static) -> S {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async move {
+            sender.send(start.await).unwrap();
+            std::future::pending::<()>().await
+        })
+    });
+    receiver.recv().unwrap()
+}
+
+fn stub_client(server: &StubServer) -> FCMDeviceGroupClient {
+    FCMDeviceGroupClient::with_url(server.url("/fcm/notification"), SENDER_ID, NoToken).unwrap()
+}
+
+#[test]
+fn group_operations_update_the_registry() {
+    let server = in_background(async { FakeFCMServer::start().await.unwrap() });
+    let registry = Arc::new(InMemoryRegistry::new());
+    let client = FCMDeviceGroupClient::with_url(server.url(), SENDER_ID, NoToken)
+        .unwrap()
+        .with_registry(registry.clone());
+
+    let group = client.create_group(name(), tokens(&["a", "b"])).unwrap();
+    let group = client.add_to_group(group, tokens(&["c"])).unwrap();
+    let group = client.remove_from_group(group, tokens(&["a"])).unwrap();
+    assert_eq!(client.get_key(name()).unwrap(), group);
+
+    let members = tokens(&["b", "c"]).into_iter().collect();
+    assert_eq!(server.group("group").unwrap().registration_ids, members);
+    assert_eq!(
+        client
+            .registry()
+            .unwrap()
+            .group("group")
+            .unwrap()
+            .unwrap()
+            .registration_ids,
+        members
+    );
+
+    let error = client.create_group(name(), tokens(&["d"])).unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(CreateGroupError::AlreadyExists)
+    ));
+}
+
+#[test]
+fn retries_like_the_async_client() {
+    let server = in_background(StubServer::sequence(vec![
+        Reply::status(503).header("Retry-After", "0"),
+        Reply::json(200, json!({"notification_key": "key"})),
+    ]));
+    let client = stub_client(&server).with_retry_policy(
+        RetryPolicy::new(3)
+            .with_initial_backoff(Duration::from_millis(1))
+            .with_max_backoff(Duration::from_millis(10)),
+    );
+    assert_eq!(client.get_key(name()).unwrap().notification_key, "key");
+    assert_eq!(server.requests().len(), 2);
+
+    // Creating is not idempotent, so a server error is not retried
+    let server = in_background(StubServer::sequence(vec![Reply::status(500)]));
+    let client = stub_client(&server).with_retry_policy(RetryPolicy::new(3));
+    client.create_group(name(), tokens(&["a"])).unwrap_err();
+    assert_eq!(server.requests().len(), 1);
+
+    let server = in_background(StubServer::sequence(vec![Reply::status(429)]));
+    let client = stub_client(&server)
+        .with_retry_policy(RetryPolicy::new(2).with_initial_backoff(Duration::from_millis(1)));
+    let error = client.get_key(name()).unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::RetriesExhausted { attempts: 2, .. }
+    ));
+}
+
+#[test]
+fn failed_batch_reports_what_was_applied() {
+    let server = in_background(StubServer::sequence(vec![
+        Reply::json(200, json!({"notification_key": "key-1"})),
+        Reply::json(200, json!({"notification_key": "key-2"})),
+        Reply::json(400, json!({"error": "no valid registration ids"})),
+    ]));
+    let client = stub_client(&server).with_max_registration_ids_per_request(2);
+
+    let error = client
+        .create_group(name(), tokens(&["a", "b", "c", "d", "e", "f", "g"]))
+        .unwrap_err();
+    let FCMDeviceGroupsRequestError::PartialBatchFailure(failure) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(failure.group.notification_key, "key-2");
+    assert_eq!(failure.applied, [tokens(&["a", "b"]), tokens(&["c", "d"])]);
+    assert_eq!(failure.failed, tokens(&["e", "f"]));
+    assert_eq!(failure.remaining, [tokens(&["g"])]);
+}
+
+#[test]
+fn invalid_success_bodies_are_errors() {
+    let server = in_background(StubServer::sequence(vec![
+        Reply::status(200).body("not json"),
+    ]));
+    let error = stub_client(&server).get_key(name()).unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::InvalidResponse(_)
+    ));
+}
+
+#[test]
+fn builder_settings_apply() {
+    // Connections are accepted by the OS but never answered
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/fcm/notification", listener.local_addr().unwrap());
+    let client = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(url)
+        .timeout(Duration::from_millis(100))
+        .build_blocking()
+        .unwrap();
+
+    let error = client.get_key(name()).unwrap_err();
+    let FCMDeviceGroupsRequestError::HttpError(error) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert!(error.is_timeout());
+
+    let server = in_background(StubServer::sequence(vec![Reply::json(
+        200,
+        json!({"notification_key": "key"}),
+    )]));
+    FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(server.url("/fcm/notification"))
+        .user_agent("batch-job")
+        .build_blocking()
+        .unwrap()
+        .get_key(name())
+        .unwrap();
+    let request = &server.requests()[0];
+    assert_eq!(request.header("user-agent"), Some("batch-job"));
+    assert_eq!(request.header("project_id"), Some(SENDER_ID));
+    assert_eq!(request.header("access_token_auth"), Some("true"));
+}
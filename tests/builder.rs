@@ -0,0 +1,157 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Reply, SENDER_ID, StubServer, name};
+use fcm_device_group::{FCMDeviceGroupClient, NoToken, error::FCMDeviceGroupsRequestError};
+use reqwest::Proxy;
+use serde_json::json;
+
+async fn key_server() -> StubServer {
+    StubServer::start(|_| Reply::json(200, json!({"notification_key": "key"}))).await
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sends_the_required_headers_and_user_agent() {
+    let server = key_server().await;
+    let client = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(server.url("/fcm/notification"))
+        .user_agent("batch-job")
+        .timeout(Duration::from_secs(10))
+        .build()
+        .unwrap();
+
+    client.get_key(name()).await.unwrap();
+    let request = &server.requests()[0];
+    assert_eq!(
+        request.path,
+        "/fcm/notification?notification_key_name=group"
+    );
+    assert_eq!(request.header("project_id"), Some(SENDER_ID));
+    assert_eq!(request.header("access_token_auth"), Some("true"));
+    assert_eq!(request.header("user-agent"), Some("batch-job"));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn requests_time_out() {
+    // Connections are accepted by the OS but never answered
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/fcm/notification", listener.local_addr().unwrap());
+    let client = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(url)
+        .timeout(Duration::from_millis(100))
+        .build()
+        .unwrap();
+
+    let error = client.get_key(name()).await.unwrap_err();
+    let FCMDeviceGroupsRequestError::HttpError(error) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert!(error.is_timeout());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn requests_go_through_the_proxy() {
+    let proxy = key_server().await;
+    let client = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url("http://fcm.invalid/fcm/notification")
+        .proxy(Proxy::http(proxy.url("")).unwrap())
+        .build()
+        .unwrap();
+
+    client.get_key(name()).await.unwrap();
+    let request = &proxy.requests()[0];
+    assert_eq!(
+        request.path,
+        "http://fcm.invalid/fcm/notification?notification_key_name=group"
+    );
+    assert_eq!(request.header("project_id"), Some(SENDER_ID));
+}
+
+/// An HTTPS server for `localhost` with a certificate issued by the test CA in `tests/data/test_ca.pem`
+#[cfg(feature = "default-tls")]
+async fn tls_server() -> std::net::SocketAddr {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        TlsAcceptor,
+        rustls::{
+            ServerConfig,
+            pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+        },
+    };
+
+    let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data");
+    let certs = CertificateDer::pem_file_iter(format!("{data}/localhost.pem"))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_file(format!("{data}/localhost.key")).unwrap();
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                // Handshakes fail when the client doesn't trust the certificate
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let body = json!({"notification_key": "key"}).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+#[cfg(feature = "default-tls")]
+#[tokio::test(flavor = "current_thread")]
+async fn trusts_added_root_certificates() {
+    let addr = tls_server().await;
+    let url = format!("https://localhost:{}/fcm/notification", addr.port());
+
+    let untrusted = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(&url)
+        .build()
+        .unwrap();
+    assert!(matches!(
+        untrusted.get_key(name()).await,
+        Err(FCMDeviceGroupsRequestError::HttpError(_))
+    ));
+
+    let ca = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/data/test_ca.pem"
+    ))
+    .unwrap();
+    let trusted = FCMDeviceGroupClient::builder(SENDER_ID, NoToken)
+        .url(&url)
+        .add_root_certificate(reqwest::Certificate::from_pem(&ca).unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(
+        trusted.get_key(name()).await.unwrap().notification_key,
+        "key"
+    );
+}
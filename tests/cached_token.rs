@@ -0,0 +1,123 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use fcm_device_group::{CachedToken, GetToken};
+
+type GetTokenOutput<'a> = Pin<
+    Box<
+        dyn Future<Output = Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + 'a,
+    >,
+>;
+
+/// Returns a new token on every call, or fails while `fail` is set
+#[derive(Clone, Default)]
+struct CountingToken {
+    calls: Arc<AtomicUsize>,
+    fail: Arc<AtomicBool>,
+}
+
+impl CountingToken {
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+impl GetToken for CountingToken {
+    fn get_token<'a>(&'a self, scopes: &'a [&str]) -> GetTokenOutput<'a> {
+        Box::pin(async move {
+            // Give concurrent callers a chance to run while the token is fetched
+            tokio::task::yield_now().await;
+            if self.fail.load(Ordering::SeqCst) {
+                return Err("token endpoint unavailable".into());
+            }
+            let call = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Some(format!("token-{call}-{}", scopes.join(","))))
+        })
+    }
+}
+
+const SCOPES: &[&str] = &["scope"];
+
+async fn token(auth: &impl GetToken) -> String {
+    auth.get_token(SCOPES).await.unwrap().unwrap()
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn reuses_the_token_until_it_expires() {
+    let inner = CountingToken::default();
+    let auth = CachedToken::new(inner.clone(), Duration::from_secs(60 * 60))
+        .with_refresh_margin(Duration::ZERO);
+
+    assert_eq!(token(&auth).await, "token-1-scope");
+    assert_eq!(token(&auth).await, "token-1-scope");
+    // Clones share the cache
+    assert_eq!(token(&auth.clone()).await, "token-1-scope");
+    assert_eq!(inner.calls(), 1);
+
+    tokio::time::advance(Duration::from_secs(60 * 60 - 1)).await;
+    assert_eq!(token(&auth).await, "token-1-scope");
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(token(&auth).await, "token-2-scope");
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn refreshes_within_the_margin() {
+    let inner = CountingToken::default();
+    let auth = CachedToken::new(inner.clone(), Duration::from_secs(60))
+        .with_refresh_margin(Duration::from_secs(60));
+
+    assert_eq!(token(&auth).await, "token-1-scope");
+    assert_eq!(token(&auth).await, "token-2-scope");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn caches_per_scopes() {
+    let inner = CountingToken::default();
+    let auth = CachedToken::new(inner.clone(), Duration::from_secs(60 * 60));
+
+    let first = auth.get_token(&["a", "b"]).await.unwrap().unwrap();
+    assert_eq!(first, "token-1-a,b");
+    // The order of the scopes doesn't matter
+    assert_eq!(auth.get_token(&["b", "a"]).await.unwrap().unwrap(), first);
+    assert_eq!(auth.get_token(&["a"]).await.unwrap().unwrap(), "token-2-a");
+    assert_eq!(inner.calls(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn concurrent_calls_share_a_refresh() {
+    let inner = CountingToken::default();
+    let auth = CachedToken::new(inner.clone(), Duration::from_secs(60 * 60));
+
+    let (first, second) = tokio::join!(token(&auth), token(&auth));
+    assert_eq!(first, second);
+    assert_eq!(inner.calls(), 1);
+}
+
+#[tokio::test(flavor = "current_thread", start_paused = true)]
+async fn failed_refresh_falls_back_to_the_unexpired_token() {
+    let inner = CountingToken::default();
+    let auth = CachedToken::new(inner.clone(), Duration::from_secs(60 * 60))
+        .with_refresh_margin(Duration::from_secs(5 * 60));
+    assert_eq!(token(&auth).await, "token-1-scope");
+
+    // Within the margin a refresh is attempted, but the token is still valid
+    tokio::time::advance(Duration::from_secs(56 * 60)).await;
+    inner.fail.store(true, Ordering::SeqCst);
+    assert_eq!(token(&auth).await, "token-1-scope");
+
+    tokio::time::advance(Duration::from_secs(4 * 60)).await;
+    assert!(auth.get_token(SCOPES).await.is_err());
+
+    inner.fail.store(false, Ordering::SeqCst);
+    assert_eq!(token(&auth).await, "token-2-scope");
+}
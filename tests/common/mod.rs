@@ -0,0 +1,241 @@
+//! Fixtures shared by the integration tests, and a scripted HTTP server for tests that need responses
+//! FakeFCMServer doesn't give.
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "fake-server")]
+use fcm_device_group::fake_server::FakeFCMServer;
+use fcm_device_group::{FCMDeviceGroupClient, NoToken, NotificationKeyName, RegistrationToken};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// Sender id of the clients created here
+pub const SENDER_ID: &str = "1234";
+
+/// The group name most tests use, `group`
+pub fn name() -> NotificationKeyName {
+    key_name("group")
+}
+
+pub fn key_name(name: &str) -> NotificationKeyName {
+    NotificationKeyName::new(name).unwrap()
+}
+
+pub fn tokens(ids: &[&str]) -> Vec<RegistrationToken> {
+    ids.iter()
+        .map(|id| RegistrationToken::new(*id).unwrap())
+        .collect()
+}
+
+/// A fake FCM server and a client talking to it
+#[cfg(feature = "fake-server")]
+pub async fn start() -> (FakeFCMServer, FCMDeviceGroupClient) {
+    let server = FakeFCMServer::start().await.unwrap();
+    let client = FCMDeviceGroupClient::with_url(server.url(), SENDER_ID, NoToken).unwrap();
+    (server, client)
+}
+
+/// Members of a group on the fake server, none if the group doesn't exist
+#[cfg(feature = "fake-server")]
+pub fn members(server: &FakeFCMServer, group: &str) -> Vec<RegistrationToken> {
+    server
+        .group(group)
+        .map(|group| group.registration_ids.into_iter().collect())
+        .unwrap_or_default()
+}
+
+/// A request received by the stub
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path including the query
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+/// A response sent by the stub
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: String::new(),
+        }
+    }
+
+    pub fn json(status: u16, body: serde_json::Value) -> Self {
+        Self::status(status)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+/// Local HTTP server answering every request with `handler`, shut down when dropped
+pub struct StubServer {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+impl StubServer {
+    pub async fn start(handler: impl Fn(&Request) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let task_requests = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let requests = task_requests.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move { serve(stream, requests, handler).await });
+            }
+        });
+        Self {
+            addr,
+            requests,
+            task,
+        }
+    }
+
+    /// Answer requests with `replies` in order, repeating the last one
+    pub async fn sequence(replies: Vec<Reply>) -> Self {
+        let next = Mutex::new(0);
+        Self::start(move |_| {
+            let mut next = next.lock().unwrap();
+            let reply = replies[(*next).min(replies.len() - 1)].clone();
+            *next += 1;
+            reply
+        })
+        .await
+    }
+
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// A client sending device group operations to the stub
+    pub fn client(&self) -> FCMDeviceGroupClient {
+        FCMDeviceGroupClient::with_url(self.url("/fcm/notification"), SENDER_ID, NoToken).unwrap()
+    }
+}
+
+impl Drop for StubServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(mut stream: TcpStream, requests: Arc<Mutex<Vec<Request>>>, handler: Arc<Handler>) {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let content_length = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    while buffer.len() < head_end + content_length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(read) => buffer.extend_from_slice(&chunk[..read]),
+        }
+    }
+
+    let request = Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&buffer[head_end..head_end + content_length]).into_owned(),
+    };
+    let reply = handler(&request);
+    requests.lock().unwrap().push(request);
+
+    let mut response = format!(
+        "HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(&reply.body);
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// A new empty directory under the system temp directory
+pub fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("fcm-device-group-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,293 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{Reply, StubServer};
+use fcm_device_group::{
+    FCMDeviceGroupClient, GetToken,
+    auth::{
+        CREDENTIALS_ENV, CredentialsSource, DefaultCredentials, METADATA_HOST_ENV, SENDER_ID_ENV,
+    },
+    error::DefaultCredentialsError,
+};
+use serde_json::json;
+use tokio::sync::{Mutex, MutexGuard};
+
+const GCLOUD_CONFIG_ENV: &str = "CLOUDSDK_CONFIG";
+
+/// Environment variables are shared by the whole process, so tests changing them take turns
+static ENV_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// The environment `DefaultCredentials::find` looks at
+#[derive(Default)]
+struct Env<'a> {
+    credentials_file: Option<&'a Path>,
+    gcloud_config: Option<&'a Path>,
+    metadata_host: Option<String>,
+    sender_id: Option<&'a str>,
+}
+
+impl Env<'_> {
+    async fn apply(self) -> MutexGuard<'static, ()> {
+        let guard = ENV_LOCK.lock().await;
+        set(CREDENTIALS_ENV, self.credentials_file.map(Path::as_os_str));
+        set(GCLOUD_CONFIG_ENV, self.gcloud_config.map(Path::as_os_str));
+        set(
+            METADATA_HOST_ENV,
+            self.metadata_host.as_deref().map(AsRef::as_ref),
+        );
+        set(SENDER_ID_ENV, self.sender_id.map(AsRef::as_ref));
+        guard
+    }
+}
+
+fn set(name: &str, value: Option<&std::ffi::OsStr>) {
+    // SAFETY: tests changing the environment hold ENV_LOCK, and nothing else reads it concurrently
+    unsafe {
+        match value {
+            Some(value) => std::env::set_var(name, value),
+            None => std::env::remove_var(name),
+        }
+    }
+}
+
+/// A directory holding gcloud user credentials, like the gcloud configuration directory
+fn gcloud_config(name: &str) -> PathBuf {
+    let dir = common::temp_dir(name);
+    std::fs::write(
+        dir.join("application_default_credentials.json"),
+        json!({
+            "type": "authorized_user",
+            "client_id": "client-id",
+            "client_secret": "client-secret",
+            "refresh_token": "refresh-token",
+            "quota_project_id": "my-project",
+        })
+        .to_string(),
+    )
+    .unwrap();
+    dir
+}
+
+/// Answer like the GCE metadata server
+async fn metadata_server() -> StubServer {
+    StubServer::start(|request| {
+        let path = request.path.split('?').next().unwrap();
+        let reply = match path {
+            "/computeMetadata/v1/project/numeric-project-id" => {
+                Reply::status(200).body("123456789\n")
+            }
+            "/computeMetadata/v1/instance/service-accounts/default/token" => Reply::json(
+                200,
+                json!({"access_token": "access-token", "expires_in": 3599, "token_type": "Bearer"}),
+            ),
+            _ => Reply::status(404),
+        };
+        reply.header("Metadata-Flavor", "Google")
+    })
+    .await
+}
+
+/// Something answering on the metadata host that isn't a metadata server
+async fn not_metadata_server() -> StubServer {
+    StubServer::start(|_| Reply::status(200).body("hello")).await
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn credentials_file_comes_first() {
+    let env_dir = gcloud_config("env-first");
+    let credentials_file = env_dir.join("application_default_credentials.json");
+    let gcloud_dir = gcloud_config("env-first-gcloud");
+    let metadata = metadata_server().await;
+    let _env = Env {
+        credentials_file: Some(&credentials_file),
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(
+        credentials.source(),
+        &CredentialsSource::EnvironmentVariable(Some(credentials_file))
+    );
+    assert!(metadata.requests().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn gcloud_file_comes_second() {
+    let gcloud_dir = gcloud_config("gcloud-second");
+    let metadata = metadata_server().await;
+    let _env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(
+        credentials.source(),
+        &CredentialsSource::GcloudFile(Some(
+            gcloud_dir.join("application_default_credentials.json")
+        ))
+    );
+    assert!(metadata.requests().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn file_credentials_need_an_explicit_sender_id() {
+    let gcloud_dir = gcloud_config("explicit-sender-id");
+    let env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    // The quota project is a project id, not the project number FCM needs
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(credentials.sender_id(), None);
+    let Err(error) = FCMDeviceGroupClient::builder_from_env().await else {
+        panic!("expected MissingSenderId");
+    };
+    assert!(matches!(
+        error,
+        DefaultCredentialsError::MissingSenderId(CredentialsSource::GcloudFile(_))
+    ));
+    assert!(error.to_string().contains(SENDER_ID_ENV), "{error}");
+    drop(env);
+
+    let _env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        sender_id: Some("987654321"),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(credentials.sender_id(), Some("987654321"));
+    FCMDeviceGroupClient::builder_from_env().await.unwrap();
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sender_id_must_be_a_project_number() {
+    let gcloud_dir = gcloud_config("project-number");
+    let _env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        sender_id: Some("my-project"),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    assert!(matches!(
+        DefaultCredentials::find().await,
+        Err(DefaultCredentialsError::InvalidSenderId(sender_id)) if sender_id == "my-project"
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn metadata_server_comes_last_and_gives_the_project_number() {
+    let gcloud_dir = common::temp_dir("metadata-last");
+    let metadata = metadata_server().await;
+    let env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(
+        credentials.source(),
+        &CredentialsSource::MetadataServer(metadata.host())
+    );
+    assert_eq!(credentials.sender_id(), Some("123456789"));
+    let token = credentials.get_token(&["scope"]).await.unwrap();
+    assert_eq!(token.as_deref(), Some("access-token"));
+    drop(env);
+
+    // An explicit sender id wins, and the server isn't probed again
+    let _env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        sender_id: Some("987654321"),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+    let requests = metadata.requests().len();
+    let credentials = DefaultCredentials::find().await.unwrap();
+    assert_eq!(credentials.sender_id(), Some("987654321"));
+    assert_eq!(metadata.requests().len(), requests);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn unusable_file_stops_the_search() {
+    let dir = common::temp_dir("unusable");
+    let credentials_file = dir.join("credentials.json");
+    std::fs::write(&credentials_file, "not json").unwrap();
+    let gcloud_dir = gcloud_config("unusable-gcloud");
+    let metadata = metadata_server().await;
+    let _env = Env {
+        credentials_file: Some(&credentials_file),
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    let Err(DefaultCredentialsError::NoCredentials(attempts)) = DefaultCredentials::find().await
+    else {
+        panic!("expected NoCredentials");
+    };
+    assert_eq!(attempts.len(), 1);
+    assert_eq!(
+        attempts[0].source,
+        CredentialsSource::EnvironmentVariable(Some(credentials_file))
+    );
+    assert!(metadata.requests().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn lists_what_was_tried_and_only_probes_once() {
+    let gcloud_dir = common::temp_dir("nothing");
+    let metadata = not_metadata_server().await;
+    let _env = Env {
+        gcloud_config: Some(&gcloud_dir),
+        metadata_host: Some(metadata.host()),
+        ..Env::default()
+    }
+    .apply()
+    .await;
+
+    let error = DefaultCredentials::find().await.unwrap_err();
+    let DefaultCredentialsError::NoCredentials(attempts) = &error else {
+        panic!("unexpected error {error:?}");
+    };
+    let sources: Vec<_> = attempts.iter().map(|attempt| &attempt.source).collect();
+    assert_eq!(
+        sources,
+        [
+            &CredentialsSource::EnvironmentVariable(None),
+            &CredentialsSource::GcloudFile(Some(
+                gcloud_dir.join("application_default_credentials.json")
+            )),
+            &CredentialsSource::MetadataServer(metadata.host()),
+        ]
+    );
+    let message = error.to_string();
+    assert!(message.contains(CREDENTIALS_ENV), "{message}");
+    assert!(message.contains(&metadata.host()), "{message}");
+    assert_eq!(metadata.requests().len(), 1);
+
+    // Later lookups reuse the result instead of waiting on the server again
+    assert!(FCMDeviceGroupClient::from_env().await.is_err());
+    assert_eq!(metadata.requests().len(), 1);
+}
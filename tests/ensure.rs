@@ -0,0 +1,72 @@
+mod common;
+
+use common::{Reply, StubServer, name, start, tokens};
+use fcm_device_group::error::{
+    EnsureGroupError, FCMDeviceGroupsRequestError, operation_errors::GetKeyError,
+};
+use serde_json::json;
+
+#[tokio::test(flavor = "current_thread")]
+async fn creates_missing_group() {
+    let (server, client) = start().await;
+    let ensured = client.ensure_group(name(), tokens(&["a"])).await.unwrap();
+
+    assert!(ensured.created);
+    assert_eq!(
+        server.group("group").unwrap().registration_ids,
+        tokens(&["a"]).into_iter().collect()
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn adds_to_existing_group() {
+    let (server, client) = start().await;
+    let existing = client.create_group(name(), tokens(&["a"])).await.unwrap();
+
+    let ensured = client.ensure_group(name(), tokens(&["b"])).await.unwrap();
+    assert!(!ensured.created);
+    assert_eq!(ensured.group, existing);
+    assert_eq!(
+        server.group("group").unwrap().registration_ids,
+        tokens(&["a", "b"]).into_iter().collect()
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn without_registration_ids_only_looks_up_the_group() {
+    let (_server, client) = start().await;
+    let error = client.ensure_group(name(), Vec::new()).await.unwrap_err();
+    assert!(matches!(
+        error,
+        EnsureGroupError::GetKey(FCMDeviceGroupsRequestError::BadRequestError(
+            GetKeyError::KeyNotFound
+        ))
+    ));
+
+    let existing = client.create_group(name(), tokens(&["a"])).await.unwrap();
+    let ensured = client.ensure_group(name(), Vec::new()).await.unwrap();
+    assert!(!ensured.created);
+    assert_eq!(ensured.group, existing);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn gives_up_when_the_group_keeps_disappearing() {
+    // Creating says the group exists, but it is gone by the time it is looked up
+    let server = StubServer::start(|request| {
+        let error = if request.method == "POST" {
+            "notification_key already exists"
+        } else {
+            "notification_key not found"
+        };
+        Reply::json(400, json!({ "error": error }))
+    })
+    .await;
+    let client = server.client();
+
+    let error = client
+        .ensure_group(name(), tokens(&["a"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, EnsureGroupError::Contended { attempts: 3 }));
+    assert_eq!(server.requests().len(), 6);
+}
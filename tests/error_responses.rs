@@ -0,0 +1,68 @@
+mod common;
+
+use common::{Reply, StubServer, name};
+use fcm_device_group::{
+    RegistrationToken,
+    error::{FCMDeviceGroupsRequestError, operation_errors::CreateGroupError},
+};
+use serde_json::json;
+
+async fn create_with_reply(reply: Reply) -> FCMDeviceGroupsRequestError<CreateGroupError> {
+    let server = StubServer::sequence(vec![reply]).await;
+    let client = server.client();
+    client
+        .create_group(name(), vec![RegistrationToken::new("a").unwrap()])
+        .await
+        .unwrap_err()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn recognized_errors_are_typed() {
+    let error = create_with_reply(Reply::json(
+        400,
+        json!({"error": "notification_key already exists"}),
+    ))
+    .await;
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(CreateGroupError::AlreadyExists)
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn unrecognized_errors_keep_the_response() {
+    let error = create_with_reply(Reply::json(400, json!({"error": "something new"}))).await;
+    let FCMDeviceGroupsRequestError::ErrorResponse(response) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(response.status, 400);
+    assert_eq!(response.body, r#"{"error":"something new"}"#);
+    assert_eq!(response.error.as_deref(), Some("something new"));
+    assert!(response.to_string().contains("something new"), "{response}");
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn non_json_error_bodies_are_kept_raw() {
+    let error = create_with_reply(Reply::status(502).body("<html>Bad Gateway</html>")).await;
+    let FCMDeviceGroupsRequestError::ErrorResponse(response) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(response.status, 502);
+    assert_eq!(response.body, "<html>Bad Gateway</html>");
+    assert_eq!(response.error, None);
+
+    // A recognized message only counts on a 400
+    let error = create_with_reply(Reply::json(
+        500,
+        json!({"error": "notification_key already exists"}),
+    ))
+    .await;
+    let FCMDeviceGroupsRequestError::ErrorResponse(response) = error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(response.status, 500);
+    assert_eq!(
+        response.error.as_deref(),
+        Some("notification_key already exists")
+    );
+}
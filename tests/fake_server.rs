@@ -0,0 +1,175 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{key_name, members, name, start, tokens};
+use fcm_device_group::{
+    FCMDeviceGroup, NotificationKey,
+    error::{
+        FCMDeviceGroupsRequestError,
+        operation_errors::{ChangeGroupMembersError, CreateGroupError, GetKeyError},
+    },
+    registry::InMemoryRegistry,
+};
+
+#[tokio::test(flavor = "current_thread")]
+async fn create_add_remove_and_get_key() {
+    let (server, client) = start().await;
+
+    let group = client
+        .create_group(name(), tokens(&["a", "b"]))
+        .await
+        .unwrap();
+    assert_eq!(group.notification_key_name, "group");
+    assert_eq!(members(&server, "group"), tokens(&["a", "b"]));
+
+    let group = client.add_to_group(group, tokens(&["c"])).await.unwrap();
+    assert_eq!(members(&server, "group"), tokens(&["a", "b", "c"]));
+
+    let group = client
+        .remove_from_group(group, tokens(&["a"]))
+        .await
+        .unwrap();
+    assert_eq!(members(&server, "group"), tokens(&["b", "c"]));
+
+    let found = client.get_key(name()).await.unwrap();
+    assert_eq!(found, group);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn add_and_remove_members_in_place() {
+    let (server, client) = start().await;
+    let mut group = client.create_group(name(), tokens(&["a"])).await.unwrap();
+
+    client.add_members(&mut group, ["b", "c"]).await.unwrap();
+    client.remove_members(&mut group, ["b"]).await.unwrap();
+    assert_eq!(members(&server, "group"), tokens(&["a", "c"]));
+
+    let error = client.add_members(&mut group, [""]).await.unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::InvalidRegistrationToken(_)
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn removing_the_last_member_deletes_the_group() {
+    let (server, client) = start().await;
+    let group = client.create_group(name(), tokens(&["a"])).await.unwrap();
+
+    client
+        .remove_from_group(group, tokens(&["a"]))
+        .await
+        .unwrap();
+    assert!(server.group("group").is_none());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn create_errors() {
+    let (_server, client) = start().await;
+    client.create_group(name(), tokens(&["a"])).await.unwrap();
+
+    let error = client
+        .create_group(name(), tokens(&["b"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(CreateGroupError::AlreadyExists)
+    ));
+
+    let error = client
+        .create_group(key_name("empty"), Vec::new())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(CreateGroupError::NoValidRegistrationIds)
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn change_members_errors() {
+    let (_server, client) = start().await;
+    let group = client.create_group(name(), tokens(&["a"])).await.unwrap();
+    let other = client
+        .create_group(key_name("other"), tokens(&["b"]))
+        .await
+        .unwrap();
+
+    let error = client
+        .remove_from_group(group.clone(), tokens(&["unknown"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(
+            ChangeGroupMembersError::NoValidRegistrationIds
+        )
+    ));
+
+    let mismatched = FCMDeviceGroup {
+        notification_key_name: group.notification_key_name.clone(),
+        notification_key: other.notification_key,
+    };
+    let error = client
+        .add_to_group(mismatched, tokens(&["c"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(
+            ChangeGroupMembersError::KeyNameAndKeyDontMatch
+        )
+    ));
+
+    let unknown = FCMDeviceGroup {
+        notification_key_name: group.notification_key_name,
+        notification_key: NotificationKey::new("unknown-key").unwrap(),
+    };
+    let error = client
+        .add_to_group(unknown, tokens(&["c"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(ChangeGroupMembersError::KeyNotFound)
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn get_key_not_found() {
+    let (_server, client) = start().await;
+    let error = client.get_key(key_name("missing")).await.unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(GetKeyError::KeyNotFound)
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn registry_follows_successful_changes() {
+    let (_server, client) = start().await;
+    let registry = Arc::new(InMemoryRegistry::new());
+    let client = client.with_registry(registry.clone());
+
+    let group = client
+        .create_group(name(), tokens(&["a", "b"]))
+        .await
+        .unwrap();
+    let group = client.add_to_group(group, tokens(&["c"])).await.unwrap();
+    client
+        .remove_from_group(group.clone(), tokens(&["unknown"]))
+        .await
+        .unwrap_err();
+    client
+        .remove_from_group(group, tokens(&["a"]))
+        .await
+        .unwrap();
+
+    let record = client.registry().unwrap().group("group").unwrap().unwrap();
+    assert_eq!(
+        record.registration_ids,
+        tokens(&["b", "c"]).into_iter().collect()
+    );
+}
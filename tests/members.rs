@@ -0,0 +1,86 @@
+mod common;
+
+use common::{Reply, StubServer, name};
+use fcm_device_group::{FCMDeviceGroup, error::FCMDeviceGroupsRequestError};
+use serde_json::json;
+
+/// Answer every operation with a new key
+async fn rotating_server() -> StubServer {
+    let count = std::sync::Mutex::new(0);
+    StubServer::start(move |_| {
+        let mut count = count.lock().unwrap();
+        *count += 1;
+        Reply::json(200, json!({"notification_key": format!("key-{count}")}))
+    })
+    .await
+}
+
+fn group() -> FCMDeviceGroup {
+    FCMDeviceGroup {
+        notification_key_name: name(),
+        notification_key: "key-0".parse().unwrap(),
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn updates_the_key_in_place() {
+    let server = rotating_server().await;
+    let client = server.client();
+    let mut group = group();
+
+    client.add_members(&mut group, ["a", "b"]).await.unwrap();
+    assert_eq!(group.notification_key, "key-1");
+    assert_eq!(group.notification_key_name, "group");
+
+    // Owned strings and references to them work too
+    let ids = vec!["a".to_string()];
+    client.remove_members(&mut group, &ids).await.unwrap();
+    assert_eq!(group.notification_key, "key-2");
+    client.add_members(&mut group, ids).await.unwrap();
+    assert_eq!(group.notification_key, "key-3");
+
+    // Each request uses the key returned by the one before it
+    let requests = server.requests();
+    let bodies: Vec<_> = requests.iter().map(|request| request.json()).collect();
+    assert_eq!(
+        bodies,
+        [
+            json!({
+                "operation": "add",
+                "notification_key_name": "group",
+                "notification_key": "key-0",
+                "registration_ids": ["a", "b"],
+            }),
+            json!({
+                "operation": "remove",
+                "notification_key_name": "group",
+                "notification_key": "key-1",
+                "registration_ids": ["a"],
+            }),
+            json!({
+                "operation": "add",
+                "notification_key_name": "group",
+                "notification_key": "key-2",
+                "registration_ids": ["a"],
+            }),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn invalid_tokens_are_rejected_before_sending() {
+    let server = rotating_server().await;
+    let mut group = group();
+
+    let error = server
+        .client()
+        .remove_members(&mut group, ["a", "not valid"])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::InvalidRegistrationToken(_)
+    ));
+    assert_eq!(group, self::group());
+    assert!(server.requests().is_empty());
+}
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, time::Duration};
+
+use fcm_device_group::message::{
+    AndroidConfig, AndroidMessagePriority, AndroidNotification, ApnsConfig, ApnsPayload, Aps,
+    ApsAlert, Message, WebpushAction, WebpushConfig, WebpushFcmOptions, WebpushNotification,
+};
+use serde_json::json;
+
+fn to_json(message: &Message) -> serde_json::Value {
+    serde_json::to_value(message).unwrap()
+}
+
+#[test]
+fn empty_message() {
+    assert_eq!(to_json(&Message::default()), json!({}));
+}
+
+#[test]
+fn builder_fills_every_platform() {
+    let message = Message::builder()
+        .title("Door opened")
+        .body("The front door was opened")
+        .image("https://example.com/door.png")
+        .data("door", "front")
+        .android_priority(AndroidMessagePriority::High)
+        .android_ttl(Duration::from_millis(3500))
+        .android_channel_id("alerts")
+        .apns_header("apns-priority", "10")
+        .aps(Aps {
+            alert: Some(ApsAlert {
+                title: Some("Door opened".to_string()),
+                ..Default::default()
+            }),
+            badge: Some(1),
+            thread_id: Some("doors".to_string()),
+            content_available: Some(1),
+            ..Default::default()
+        })
+        .webpush_header("Urgency", "high")
+        .webpush_action(WebpushAction {
+            action: "open".to_string(),
+            title: "Open".to_string(),
+            icon: None,
+        })
+        .build();
+
+    assert_eq!(
+        to_json(&message),
+        json!({
+            "notification": {
+                "title": "Door opened",
+                "body": "The front door was opened",
+                "image": "https://example.com/door.png",
+            },
+            "data": {"door": "front"},
+            "android": {
+                "priority": "HIGH",
+                "ttl": "3.5s",
+                "notification": {"channel_id": "alerts"},
+            },
+            "apns": {
+                "headers": {"apns-priority": "10"},
+                "payload": {
+                    "aps": {
+                        "alert": {"title": "Door opened"},
+                        "badge": 1,
+                        "thread-id": "doors",
+                        "content-available": 1,
+                    },
+                },
+            },
+            "webpush": {
+                "headers": {"Urgency": "high"},
+                "notification": {
+                    "actions": [{"action": "open", "title": "Open"}],
+                },
+            },
+        })
+    );
+}
+
+#[test]
+fn android_config() {
+    let message = Message::builder()
+        .android(AndroidConfig {
+            collapse_key: Some("door".to_string()),
+            priority: Some(AndroidMessagePriority::Normal),
+            ttl: Some(Duration::from_secs(60)),
+            notification: Some(AndroidNotification {
+                icon: Some("ic_door".to_string()),
+                color: Some("#ff0000".to_string()),
+                sound: Some("default".to_string()),
+                tag: Some("door".to_string()),
+                click_action: Some("OPEN_DOOR".to_string()),
+                ..Default::default()
+            }),
+        })
+        .build();
+
+    assert_eq!(
+        to_json(&message),
+        json!({
+            "android": {
+                "collapse_key": "door",
+                "priority": "NORMAL",
+                "ttl": "60s",
+                "notification": {
+                    "icon": "ic_door",
+                    "color": "#ff0000",
+                    "sound": "default",
+                    "tag": "door",
+                    "click_action": "OPEN_DOOR",
+                },
+            },
+        })
+    );
+}
+
+#[test]
+fn ttl_keeps_sub_second_precision() {
+    for (ttl, expected) in [
+        (Duration::ZERO, "0s"),
+        (Duration::from_nanos(1), "0.000000001s"),
+        (Duration::from_millis(1250), "1.25s"),
+    ] {
+        let message = Message::builder().android_ttl(ttl).build();
+        assert_eq!(to_json(&message)["android"]["ttl"], expected);
+    }
+}
+
+#[test]
+fn apns_and_webpush_configs() {
+    let message = Message::builder()
+        .apns(ApnsConfig {
+            headers: HashMap::from([("apns-expiration".to_string(), "0".to_string())]),
+            payload: Some(ApnsPayload {
+                aps: Aps {
+                    sound: Some("default".to_string()),
+                    category: Some("DOOR".to_string()),
+                    mutable_content: Some(1),
+                    ..Default::default()
+                },
+            }),
+        })
+        .webpush(WebpushConfig {
+            data: HashMap::from([("door".to_string(), "back".to_string())]),
+            notification: Some(WebpushNotification {
+                title: Some("Door opened".to_string()),
+                icon: Some("https://example.com/icon.png".to_string()),
+                ..Default::default()
+            }),
+            fcm_options: Some(WebpushFcmOptions {
+                link: Some("https://example.com/doors".to_string()),
+            }),
+            ..Default::default()
+        })
+        .build();
+
+    assert_eq!(
+        to_json(&message),
+        json!({
+            "apns": {
+                "headers": {"apns-expiration": "0"},
+                "payload": {
+                    "aps": {
+                        "sound": "default",
+                        "category": "DOOR",
+                        "mutable-content": 1,
+                    },
+                },
+            },
+            "webpush": {
+                "data": {"door": "back"},
+                "notification": {
+                    "title": "Door opened",
+                    "icon": "https://example.com/icon.png",
+                },
+                "fcm_options": {"link": "https://example.com/doors"},
+            },
+        })
+    );
+}
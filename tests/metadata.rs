@@ -0,0 +1,113 @@
+mod common;
+
+use common::{Reply, StubServer};
+use fcm_device_group::{GetToken, auth::MetadataServerAuth, error::MetadataServerError};
+use serde_json::json;
+
+/// Answer like the GCE metadata server
+async fn metadata_server() -> StubServer {
+    StubServer::start(|request| {
+        if request.header("metadata-flavor") != Some("Google") {
+            return Reply::status(403);
+        }
+        let path = request.path.split('?').next().unwrap();
+        let reply = match path {
+            "/computeMetadata/v1/project/project-id" => Reply::status(200).body("my-project\n"),
+            "/computeMetadata/v1/project/numeric-project-id" => {
+                Reply::status(200).body("123456789\n")
+            }
+            "/computeMetadata/v1/instance/service-accounts/default/token"
+            | "/computeMetadata/v1/instance/service-accounts/fcm@my-project.iam.gserviceaccount.com/token" => {
+                Reply::json(
+                    200,
+                    json!({"access_token": "access-token", "expires_in": 3599, "token_type": "Bearer"}),
+                )
+            }
+            _ => Reply::status(404).body("not found"),
+        };
+        reply.header("Metadata-Flavor", "Google")
+    })
+    .await
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn gets_tokens() {
+    let server = metadata_server().await;
+    let auth = MetadataServerAuth::new().with_host(server.host());
+
+    let token = auth.get_token(&["scope-a", "scope-b"]).await.unwrap();
+    assert_eq!(token.as_deref(), Some("access-token"));
+    auth.get_token(&["scope-a", "scope-b"]).await.unwrap();
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].path,
+        "/computeMetadata/v1/instance/service-accounts/default/token?scopes=scope-a%2Cscope-b"
+    );
+    assert_eq!(requests[0].header("metadata-flavor"), Some("Google"));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn uses_the_given_service_account() {
+    let server = metadata_server().await;
+    let auth = MetadataServerAuth::new()
+        .with_host(server.host())
+        .with_service_account("fcm@my-project.iam.gserviceaccount.com");
+
+    auth.get_token(&[]).await.unwrap();
+    assert_eq!(
+        server.requests()[0].path,
+        "/computeMetadata/v1/instance/service-accounts/fcm@my-project.iam.gserviceaccount.com/token"
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn gets_and_caches_project_ids() {
+    let server = metadata_server().await;
+    let auth = MetadataServerAuth::new().with_host(server.host());
+
+    assert_eq!(auth.project_id().await.unwrap(), "my-project");
+    assert_eq!(auth.numeric_project_id().await.unwrap(), "123456789");
+    // Clones share the cached ids
+    assert_eq!(
+        auth.clone().numeric_project_id().await.unwrap(),
+        "123456789"
+    );
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn rejects_servers_without_the_flavor_header() {
+    // Something else answering on the metadata host, such as a captive portal
+    let server = StubServer::start(|_| {
+        Reply::json(
+            200,
+            json!({"access_token": "not-a-token", "expires_in": 3600}),
+        )
+    })
+    .await;
+    let auth = MetadataServerAuth::new().with_host(server.host());
+
+    assert!(matches!(
+        auth.numeric_project_id().await,
+        Err(MetadataServerError::NotMetadataServer)
+    ));
+    assert!(auth.get_token(&["scope"]).await.is_err());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reports_error_responses() {
+    let server = metadata_server().await;
+    let auth = MetadataServerAuth::new()
+        .with_host(server.host())
+        .with_service_account("missing");
+
+    let error = auth.get_token(&["scope"]).await.unwrap_err();
+    let error = error.downcast::<MetadataServerError>().unwrap();
+    let MetadataServerError::ErrorResponse { status, body } = *error else {
+        panic!("unexpected error {error:?}");
+    };
+    assert_eq!(status, 404);
+    assert_eq!(body, "not found");
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{key_name, name, start, tokens};
+use fcm_device_group::{
+    DeviceGroupApi, FCMDeviceGroup, Operation, OperationResponse, RegistrationToken,
+    error::{
+        FCMDeviceGroupsRequestError,
+        operation_errors::{ChangeGroupMembersError, GetKeyError},
+    },
+    mock::{MockCall, MockDeviceGroupApi},
+};
+
+/// Application code written against the trait: add a device to the user's group, creating it if needed
+async fn register_device(
+    api: &impl DeviceGroupApi,
+    user: &str,
+    token: RegistrationToken,
+) -> FCMDeviceGroup {
+    match api.get_key(key_name(user)).await {
+        Ok(group) => api.add_to_group(group, vec![token]).await.unwrap(),
+        Err(FCMDeviceGroupsRequestError::BadRequestError(GetKeyError::KeyNotFound)) => {
+            api.create_group(key_name(user), vec![token]).await.unwrap()
+        }
+        Err(e) => panic!("unexpected error {e:?}"),
+    }
+}
+
+async fn register_two_devices(api: &impl DeviceGroupApi) {
+    let created = register_device(api, "user", tokens(&["a"]).remove(0)).await;
+    let updated = register_device(api, "user", tokens(&["b"]).remove(0)).await;
+    assert_eq!(created, updated);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn application_code_runs_against_the_client_and_the_mock() {
+    let (server, client) = start().await;
+    let mock = MockDeviceGroupApi::new();
+
+    register_two_devices(&client).await;
+    register_two_devices(&mock).await;
+    assert_eq!(
+        server.group("user").unwrap().registration_ids,
+        tokens(&["a", "b"]).into_iter().collect()
+    );
+    assert_eq!(
+        mock.calls(),
+        [
+            MockCall::GetKey(key_name("user")),
+            MockCall::CreateGroup {
+                notification_key_name: key_name("user"),
+                registration_ids: tokens(&["a"]),
+            },
+            MockCall::GetKey(key_name("user")),
+            MockCall::AddToGroup {
+                group: FCMDeviceGroup {
+                    notification_key_name: key_name("user"),
+                    notification_key: "mock-notification-key-1".parse().unwrap(),
+                },
+                registration_ids: tokens(&["b"]),
+            },
+        ]
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn unscripted_calls_succeed() {
+    let mock = MockDeviceGroupApi::new();
+    let error = mock.get_key(name()).await.unwrap_err();
+    assert!(matches!(
+        error,
+        FCMDeviceGroupsRequestError::BadRequestError(GetKeyError::KeyNotFound)
+    ));
+
+    let group = mock.create_group(name(), tokens(&["a"])).await.unwrap();
+    assert_eq!(group.notification_key, "mock-notification-key-1");
+    assert_eq!(mock.get_key(name()).await.unwrap(), group);
+    assert_eq!(
+        mock.add_to_group(group.clone(), tokens(&["b"]))
+            .await
+            .unwrap(),
+        group
+    );
+    assert_eq!(
+        mock.remove_from_group(group.clone(), tokens(&["b"]))
+            .await
+            .unwrap(),
+        group
+    );
+
+    let created = mock
+        .apply(Operation::Create {
+            notification_key_name: key_name("other"),
+            registration_ids: tokens(&["a"]),
+        })
+        .await
+        .unwrap();
+    assert_eq!(created.notification_key, "mock-notification-key-2");
+    assert_eq!(
+        mock.get_key(key_name("other"))
+            .await
+            .unwrap()
+            .notification_key,
+        created.notification_key
+    );
+    assert_eq!(mock.calls().len(), 7);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn queued_responses_are_returned_in_order() {
+    let mock = MockDeviceGroupApi::new();
+    let group = FCMDeviceGroup {
+        notification_key_name: name(),
+        notification_key: "key-2".parse().unwrap(),
+    };
+    mock.queue_add_to_group(Ok(group.clone()));
+    mock.queue_add_to_group(Err(ChangeGroupMembersError::NoValidRegistrationIds.into()));
+    mock.queue_remove_from_group(Err(ChangeGroupMembersError::KeyNotFound.into()));
+    mock.queue_apply(Ok(OperationResponse {
+        notification_key: "key-3".parse().unwrap(),
+    }));
+    mock.queue_get_key(Err(FCMDeviceGroupsRequestError::MissingKeyName));
+
+    let original = FCMDeviceGroup {
+        notification_key_name: name(),
+        notification_key: "key-1".parse().unwrap(),
+    };
+    assert_eq!(
+        mock.add_to_group(original.clone(), tokens(&["a"]))
+            .await
+            .unwrap(),
+        group
+    );
+    assert!(matches!(
+        mock.add_to_group(original.clone(), tokens(&["a"])).await,
+        Err(FCMDeviceGroupsRequestError::BadRequestError(
+            ChangeGroupMembersError::NoValidRegistrationIds
+        ))
+    ));
+    // The queue is empty again, so the group comes back unchanged
+    assert_eq!(
+        mock.add_to_group(original.clone(), tokens(&["a"]))
+            .await
+            .unwrap(),
+        original
+    );
+    assert!(matches!(
+        mock.remove_from_group(original.clone(), tokens(&["a"]))
+            .await,
+        Err(FCMDeviceGroupsRequestError::BadRequestError(
+            ChangeGroupMembersError::KeyNotFound
+        ))
+    ));
+    let operation = Operation::Remove {
+        notification_key_name: None,
+        notification_key: "key-1".parse().unwrap(),
+        registration_ids: tokens(&["a"]),
+    };
+    let response = mock.apply(operation.clone()).await.unwrap();
+    assert_eq!(response.notification_key, "key-3");
+    assert!(matches!(
+        mock.get_key(name()).await,
+        Err(FCMDeviceGroupsRequestError::MissingKeyName)
+    ));
+    assert_eq!(mock.calls()[4], MockCall::Apply(operation));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn futures_can_be_spawned() {
+    let mock = Arc::new(MockDeviceGroupApi::new());
+    let task_mock = mock.clone();
+    let group = tokio::spawn(async move {
+        task_mock
+            .create_group(name(), tokens(&["a"]))
+            .await
+            .unwrap()
+    })
+    .await
+    .unwrap();
+    assert_eq!(mock.get_key(name()).await.unwrap(), group);
+}
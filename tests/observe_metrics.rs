@@ -0,0 +1,114 @@
+mod common;
+
+use common::{Reply, StubServer, name, start, tokens};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+
+type Labels = Vec<(String, String)>;
+
+/// Metrics recorded since the last snapshot, as `(name, labels, value)`
+fn recorded(snapshotter: &Snapshotter) -> Vec<(String, Labels, DebugValue)> {
+    let mut recorded: Vec<_> = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let key = key.key();
+            let labels = key
+                .labels()
+                .map(|label| (label.key().to_string(), label.value().to_string()))
+                .collect();
+            (key.name().to_string(), labels, value)
+        })
+        .collect();
+    recorded.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+    recorded
+}
+
+fn labels(pairs: &[(&str, &str)]) -> Labels {
+    pairs
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+fn histogram_len(value: &DebugValue) -> usize {
+    match value {
+        DebugValue::Histogram(values) => values.len(),
+        value => panic!("expected a histogram, got {value:?}"),
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn records_outcomes_latency_and_batch_size() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+    let (_server, client) = start().await;
+
+    client
+        .create_group(name(), tokens(&["a", "b", "c"]))
+        .await
+        .unwrap();
+    client
+        .create_group(name(), tokens(&["d"]))
+        .await
+        .unwrap_err();
+
+    let recorded = recorded(&snapshotter);
+    let names: Vec<_> = recorded
+        .iter()
+        .map(|(name, labels, _)| (name.as_str(), labels.clone()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            (
+                "fcm_device_group_batch_size",
+                labels(&[("operation", "create")])
+            ),
+            (
+                "fcm_device_group_request_duration_seconds",
+                labels(&[("operation", "create")])
+            ),
+            (
+                "fcm_device_group_requests_total",
+                labels(&[("operation", "create"), ("outcome", "already_exists")])
+            ),
+            (
+                "fcm_device_group_requests_total",
+                labels(&[("operation", "create"), ("outcome", "success")])
+            ),
+        ]
+    );
+    let DebugValue::Histogram(batch_sizes) = &recorded[0].2 else {
+        panic!("expected a histogram, got {:?}", recorded[0].2);
+    };
+    let batch_sizes: Vec<f64> = batch_sizes.iter().map(|size| size.into_inner()).collect();
+    assert_eq!(batch_sizes, [3.0, 1.0]);
+    assert_eq!(histogram_len(&recorded[1].2), 2);
+    assert_eq!(recorded[2].2, DebugValue::Counter(1));
+    assert_eq!(recorded[3].2, DebugValue::Counter(1));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn server_errors_are_an_outcome() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let _guard = metrics::set_default_local_recorder(&recorder);
+    let server = StubServer::sequence(vec![Reply::status(503)]).await;
+    let client = server.client();
+
+    client.get_key(name()).await.unwrap_err();
+    let recorded = recorded(&snapshotter);
+    // Looking up a key changes no members, so it has no batch size
+    assert_eq!(recorded.len(), 2);
+    assert_eq!(recorded[0].0, "fcm_device_group_request_duration_seconds");
+    assert_eq!(recorded[0].1, labels(&[("operation", "get_key")]));
+    assert_eq!(histogram_len(&recorded[0].2), 1);
+    assert_eq!(recorded[1].0, "fcm_device_group_requests_total");
+    assert_eq!(
+        recorded[1].1,
+        labels(&[("operation", "get_key"), ("outcome", "server_error")])
+    );
+    assert_eq!(recorded[1].2, DebugValue::Counter(1));
+}
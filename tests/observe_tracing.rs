@@ -0,0 +1,125 @@
+mod common;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+use common::{name, start, tokens};
+use tracing::{
+    Event, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{
+    Registry,
+    layer::{Context, Layer, SubscriberExt},
+};
+
+type Fields = BTreeMap<String, String>;
+
+/// Records the fields of every `fcm_device_group` span, and of every event
+#[derive(Clone, Default)]
+struct Capture {
+    open: Arc<Mutex<HashMap<u64, Fields>>>,
+    closed: Arc<Mutex<Vec<Fields>>>,
+    events: Arc<Mutex<Vec<Fields>>>,
+}
+
+struct Visitor<'a>(&'a mut Fields);
+
+impl Visit for Visitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}"));
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Capture {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        if attrs.metadata().name() != "fcm_device_group" {
+            return;
+        }
+        let mut fields = Fields::new();
+        attrs.record(&mut Visitor(&mut fields));
+        self.open.lock().unwrap().insert(id.into_u64(), fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(fields) = self.open.lock().unwrap().get_mut(&id.into_u64()) {
+            values.record(&mut Visitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = Fields::new();
+        event.record(&mut Visitor(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(fields) = self.open.lock().unwrap().remove(&id.into_u64()) {
+            self.closed.lock().unwrap().push(fields);
+        }
+    }
+}
+
+impl Capture {
+    fn take_spans(&self) -> Vec<Fields> {
+        std::mem::take(&mut self.closed.lock().unwrap())
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn calls_run_in_a_span_without_ids_or_keys() {
+    let capture = Capture::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(capture.clone()));
+    let (_server, client) = start().await;
+
+    let group = client
+        .create_group(name(), tokens(&["secret-token-1", "secret-token-2"]))
+        .await
+        .unwrap();
+    let spans = capture.take_spans();
+    assert_eq!(spans.len(), 1);
+    let span = &spans[0];
+    assert_eq!(span["operation"], "create");
+    assert_eq!(span["notification_key_name"], "group");
+    assert_eq!(span["registration_ids"], "2");
+    assert_eq!(span["status"], "200");
+    assert!(span["latency_ms"].parse::<u64>().is_ok());
+    assert!(!span.contains_key("error"));
+
+    client
+        .create_group(name(), tokens(&["secret-token-3"]))
+        .await
+        .unwrap_err();
+    let spans = capture.take_spans();
+    assert_eq!(spans[0]["status"], "400");
+    assert_eq!(spans[0]["error"], "already_exists");
+
+    client.get_key(name()).await.unwrap();
+    let spans = capture.take_spans();
+    assert_eq!(spans[0]["operation"], "get_key");
+    assert!(!spans[0].contains_key("registration_ids"));
+
+    // Neither spans nor the events logged in them mention registration ids or keys
+    client
+        .add_to_group(group.clone(), tokens(&["secret-token-4"]))
+        .await
+        .unwrap();
+    let mut recorded = capture.take_spans();
+    recorded.extend(capture.events.lock().unwrap().iter().cloned());
+    assert!(!recorded.is_empty());
+    let recorded = format!("{recorded:?}");
+    assert!(!recorded.contains("secret-token"), "{recorded}");
+    assert!(
+        !recorded.contains(group.notification_key.as_str()),
+        "{recorded}"
+    );
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{Reply, StubServer, members, name, tokens};
+use fcm_device_group::{
+    FCMDeviceGroupClient, RegistrationToken, fake_server::FakeFCMServer,
+    registry::InMemoryRegistry, sharding::MAX_GROUP_MEMBERS,
+};
+use serde_json::json;
+
+/// A fake server and a client with a registry, so reconcile knows the members
+async fn start() -> (FakeFCMServer, FCMDeviceGroupClient) {
+    let (server, client) = common::start().await;
+    (
+        server,
+        client.with_registry(Arc::new(InMemoryRegistry::new())),
+    )
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn creates_missing_group() {
+    let (server, client) = start().await;
+    let report = client.reconcile(name(), tokens(&["a", "b"])).await.unwrap();
+
+    assert!(report.created);
+    assert!(report.members_known);
+    assert_eq!(report.added, tokens(&["a", "b"]));
+    assert!(report.removed.is_empty());
+    assert_eq!(
+        report.group.unwrap().notification_key,
+        server.group("group").unwrap().notification_keys[0]
+    );
+    assert_eq!(members(&server, "group"), tokens(&["a", "b"]));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn nothing_to_do_for_missing_group_without_members() {
+    let (server, client) = start().await;
+    let report = client.reconcile(name(), Vec::new()).await.unwrap();
+
+    assert!(report.is_unchanged());
+    assert!(report.members_known);
+    assert_eq!(report.group, None);
+    assert!(server.groups().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn adds_and_removes_the_difference() {
+    let (server, client) = start().await;
+    client
+        .create_group(name(), tokens(&["a", "b"]))
+        .await
+        .unwrap();
+
+    let report = client.reconcile(name(), tokens(&["b", "c"])).await.unwrap();
+    assert!(!report.created);
+    assert!(report.members_known);
+    assert_eq!(report.added, tokens(&["c"]));
+    assert_eq!(report.removed, tokens(&["a"]));
+    assert!(report.group.is_some());
+    assert_eq!(members(&server, "group"), tokens(&["b", "c"]));
+
+    let report = client.reconcile(name(), tokens(&["b", "c"])).await.unwrap();
+    assert!(report.is_unchanged());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reconciling_to_no_members_deletes_the_group() {
+    let (server, client) = start().await;
+    client
+        .create_group(name(), tokens(&["a", "b"]))
+        .await
+        .unwrap();
+
+    let report = client.reconcile(name(), Vec::new()).await.unwrap();
+    assert_eq!(report.removed, tokens(&["a", "b"]));
+    assert_eq!(report.group, None);
+    assert!(server.group("group").is_none());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reports_unknown_members_without_a_registry() {
+    let (server, client) = common::start().await;
+    client
+        .create_group(name(), tokens(&["a", "b"]))
+        .await
+        .unwrap();
+
+    let report = client.reconcile(name(), tokens(&["b", "c"])).await.unwrap();
+    assert!(!report.members_known);
+    assert_eq!(report.added, tokens(&["b", "c"]));
+    assert!(report.removed.is_empty());
+    // "a" can't be found without knowing the members
+    assert_eq!(members(&server, "group"), tokens(&["a", "b", "c"]));
+}
+
+fn numbered(prefix: &str) -> Vec<RegistrationToken> {
+    (0..MAX_GROUP_MEMBERS)
+        .map(|i| RegistrationToken::new(format!("{prefix}-{i:02}")).unwrap())
+        .collect()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn swaps_a_full_group_without_going_over_the_limit() {
+    let (server, client) = start().await;
+    client.create_group(name(), numbered("old")).await.unwrap();
+
+    // The fake server rejects changes that would leave more than MAX_GROUP_MEMBERS in the group
+    let report = client.reconcile(name(), numbered("new")).await.unwrap();
+    assert_eq!(report.added, numbered("new"));
+    assert_eq!(report.removed, numbered("old"));
+    assert!(report.group.is_some());
+    assert_eq!(members(&server, "group"), numbered("new"));
+
+    // Half of the members swapped
+    let mut desired = numbered("new");
+    desired.truncate(MAX_GROUP_MEMBERS / 2);
+    desired.extend(numbered("other").into_iter().take(MAX_GROUP_MEMBERS / 2));
+    client.reconcile(name(), desired.clone()).await.unwrap();
+    desired.sort();
+    assert_eq!(members(&server, "group"), desired);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn adds_to_a_group_created_concurrently() {
+    // The group is missing when looked up, but someone else creates it before us
+    let server = StubServer::sequence(vec![
+        Reply::json(400, json!({"error": "notification_key not found"})),
+        Reply::json(400, json!({"error": "notification_key already exists"})),
+        Reply::json(200, json!({"notification_key": "key"})),
+        Reply::json(200, json!({"notification_key": "key"})),
+    ])
+    .await;
+    let client = server.client();
+
+    let report = client.reconcile(name(), tokens(&["a"])).await.unwrap();
+    assert!(!report.created);
+    assert!(!report.members_known);
+    assert_eq!(report.added, tokens(&["a"]));
+    assert_eq!(report.group.unwrap().notification_key, "key");
+    let requests = server.requests();
+    assert_eq!(requests.len(), 4);
+    assert_eq!(requests[3].json()["operation"], "add");
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use std::fs;
+
+use common::{name, tokens};
+use fcm_device_group::{
+    FCMDeviceGroup, GroupRegistry,
+    registry::{GroupRecord, InMemoryRegistry, JsonFileRegistry, MembershipChange},
+};
+
+fn group(key: &str) -> FCMDeviceGroup {
+    FCMDeviceGroup {
+        notification_key_name: name(),
+        notification_key: key.parse().unwrap(),
+    }
+}
+
+/// Record a create, an add with a new key and a remove
+fn record_changes(registry: &dyn GroupRegistry) {
+    registry
+        .record(
+            &group("key-1"),
+            MembershipChange::Create,
+            &tokens(&["a", "b"]),
+        )
+        .unwrap();
+    registry
+        .record(&group("key-2"), MembershipChange::Add, &tokens(&["c"]))
+        .unwrap();
+    registry
+        .record(&group("key-2"), MembershipChange::Remove, &tokens(&["a"]))
+        .unwrap();
+}
+
+fn expected_record() -> GroupRecord {
+    GroupRecord {
+        notification_key_name: name(),
+        notification_keys: vec!["key-1".parse().unwrap(), "key-2".parse().unwrap()],
+        registration_ids: tokens(&["b", "c"]).into_iter().collect(),
+    }
+}
+
+fn assert_forgets_empty_groups(registry: &dyn GroupRegistry) {
+    registry
+        .record(
+            &group("key-2"),
+            MembershipChange::Remove,
+            &tokens(&["b", "c"]),
+        )
+        .unwrap();
+    assert_eq!(registry.group("group").unwrap(), None);
+    assert!(registry.groups().unwrap().is_empty());
+}
+
+#[test]
+fn in_memory_registry() {
+    let registry = InMemoryRegistry::new();
+    record_changes(&registry);
+    assert_eq!(registry.group("group").unwrap(), Some(expected_record()));
+    assert_eq!(expected_record().notification_key().unwrap(), "key-2");
+    assert_forgets_empty_groups(&registry);
+}
+
+#[test]
+fn json_file_registry_round_trip() {
+    let path = common::temp_dir("json-round-trip").join("groups.json");
+    {
+        let registry = JsonFileRegistry::open(&path).unwrap();
+        assert!(registry.groups().unwrap().is_empty());
+        record_changes(&registry);
+    }
+
+    let registry = JsonFileRegistry::open(&path).unwrap();
+    assert_eq!(registry.groups().unwrap(), [expected_record()]);
+    assert_forgets_empty_groups(&registry);
+    assert!(
+        JsonFileRegistry::open(&path)
+            .unwrap()
+            .groups()
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn json_file_registry_keeps_memory_and_file_in_sync_when_saving_fails() {
+    let dir = common::temp_dir("json-save-fails");
+    let registry = JsonFileRegistry::open(dir.join("groups.json")).unwrap();
+    record_changes(&registry);
+
+    fs::remove_dir_all(&dir).unwrap();
+    registry
+        .record(&group("key-3"), MembershipChange::Add, &tokens(&["d"]))
+        .unwrap_err();
+    assert_eq!(registry.group("group").unwrap(), Some(expected_record()));
+}
+
+#[test]
+fn json_file_registry_rejects_invalid_files() {
+    let path = common::temp_dir("json-invalid").join("groups.json");
+    fs::write(&path, "not json").unwrap();
+    assert!(JsonFileRegistry::open(&path).is_err());
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use fcm_device_group::{GroupRegistry, Operation, OperationResponse, registry::SqliteRegistry};
+
+    use super::*;
+
+    #[test]
+    fn sqlite_registry_round_trip() {
+        let path = common::temp_dir("sqlite-round-trip").join("groups.sqlite");
+        {
+            let registry = SqliteRegistry::open(&path).unwrap();
+            record_changes(&registry);
+        }
+
+        let registry = SqliteRegistry::open(&path).unwrap();
+        assert_eq!(registry.groups().unwrap(), [expected_record()]);
+        assert_forgets_empty_groups(&registry);
+    }
+
+    #[test]
+    fn sqlite_registry_records_operations() {
+        let registry = SqliteRegistry::open_in_memory().unwrap();
+        let operation = Operation::Create {
+            notification_key_name: name(),
+            registration_ids: tokens(&["a"]),
+        };
+        let response = OperationResponse {
+            notification_key: "key-1".parse().unwrap(),
+        };
+        registry
+            .record_operation(&operation, Ok(&response))
+            .unwrap();
+        registry
+            .record_operation(&operation, Err("notification_key already exists"))
+            .unwrap();
+
+        let operations = registry.operations().unwrap();
+        assert_eq!(operations.len(), 2);
+        assert_eq!(operations[0].operation, operation);
+        assert_eq!(operations[0].response, Some(response));
+        assert_eq!(operations[0].error, None);
+        assert_eq!(operations[1].response, None);
+        assert_eq!(
+            operations[1].error.as_deref(),
+            Some("notification_key already exists")
+        );
+    }
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{Reply, StubServer, name};
+use fcm_device_group::{
+    FCMDeviceGroupClient, Operation, RegistrationToken, RetryPolicy,
+    error::FCMDeviceGroupsRequestError,
+};
+use serde_json::json;
+
+fn client(server: &StubServer) -> FCMDeviceGroupClient {
+    server.client().with_retry_policy(
+        RetryPolicy::new(3)
+            .with_initial_backoff(Duration::from_millis(1))
+            .with_max_backoff(Duration::from_millis(10)),
+    )
+}
+
+fn ok() -> Reply {
+    Reply::json(200, json!({"notification_key": "key"}))
+}
+
+fn create() -> Operation {
+    Operation::Create {
+        notification_key_name: name(),
+        registration_ids: vec![RegistrationToken::new("a").unwrap()],
+    }
+}
+
+fn change(add: bool) -> Operation {
+    let notification_key_name = Some(name());
+    let notification_key = "key".parse().unwrap();
+    let registration_ids = vec![RegistrationToken::new("a").unwrap()];
+    if add {
+        Operation::Add {
+            notification_key_name,
+            notification_key,
+            registration_ids,
+        }
+    } else {
+        Operation::Remove {
+            notification_key_name,
+            notification_key,
+            registration_ids,
+        }
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn retries_too_many_requests_and_unavailable() {
+    let server = StubServer::sequence(vec![Reply::status(429), Reply::status(503), ok()]).await;
+    // Safe to retry even for a create, FCM did not process the request
+    let response = client(&server).apply(create()).await.unwrap();
+    assert_eq!(response.notification_key, "key");
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn retries_server_errors_of_idempotent_requests() {
+    let server = StubServer::sequence(vec![Reply::status(500), Reply::status(502), ok()]).await;
+    client(&server).get_key(name()).await.unwrap();
+    assert_eq!(server.requests().len(), 3);
+
+    let server = StubServer::sequence(vec![Reply::status(500), ok()]).await;
+    client(&server).apply(change(true)).await.unwrap();
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn does_not_retry_server_errors_of_non_idempotent_requests() {
+    for operation in [create(), change(false)] {
+        let server = StubServer::sequence(vec![Reply::status(500), ok()]).await;
+        let error = client(&server).apply(operation).await.unwrap_err();
+        assert!(
+            matches!(&error, FCMDeviceGroupsRequestError::ErrorResponse(response) if response.status == 500),
+            "{error:?}"
+        );
+        assert_eq!(server.requests().len(), 1);
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn does_not_retry_bad_requests() {
+    let server = StubServer::sequence(vec![
+        Reply::json(400, json!({"error": "notification_key not found"})),
+        ok(),
+    ])
+    .await;
+    client(&server).get_key(name()).await.unwrap_err();
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reports_attempts_when_retries_are_exhausted() {
+    let server = StubServer::sequence(vec![Reply::status(503)]).await;
+    let error = client(&server).get_key(name()).await.unwrap_err();
+    match error {
+        FCMDeviceGroupsRequestError::RetriesExhausted { attempts, source } => {
+            assert_eq!(attempts, 3);
+            assert!(matches!(
+                *source,
+                FCMDeviceGroupsRequestError::ErrorResponse(response) if response.status == 503
+            ));
+        }
+        error => panic!("unexpected error {error:?}"),
+    }
+    assert_eq!(server.requests().len(), 3);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn caps_retry_after_at_max_backoff() {
+    let server =
+        StubServer::sequence(vec![Reply::status(503).header("Retry-After", "3600"), ok()]).await;
+    let started = Instant::now();
+    client(&server).get_key(name()).await.unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(server.requests().len(), 2);
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::time::Duration;
+
+use common::{Reply, StubServer};
+use fcm_device_group::{
+    FCMDeviceGroup, FCMDeviceGroupClient, NoToken, NotificationKey, NotificationKeyName,
+    RetryPolicy, error::FCMDeviceGroupsRequestError, message::Message,
+};
+use reqwest::Url;
+use serde_json::json;
+
+const SEND_PATH: &str = "/v1/projects/1234/messages:send";
+
+fn client(server: &StubServer) -> FCMDeviceGroupClient {
+    FCMDeviceGroupClient::builder("1234", "token".to_string())
+        .send_url(Url::parse(&server.url(SEND_PATH)).unwrap())
+        .retry_policy(RetryPolicy::new(3).with_initial_backoff(Duration::from_millis(1)))
+        .build()
+        .unwrap()
+}
+
+fn group() -> FCMDeviceGroup {
+    FCMDeviceGroup {
+        notification_key_name: NotificationKeyName::new("group").unwrap(),
+        notification_key: NotificationKey::new("notification-key").unwrap(),
+    }
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn sends_to_the_notification_key_with_the_v1_api() {
+    let server = StubServer::sequence(vec![Reply::json(
+        200,
+        json!({"name": "projects/1234/messages/0:1500415314455276%31bd1c9631bd1c96"}),
+    )])
+    .await;
+    let message = Message::builder()
+        .title("Hello")
+        .data("door", "front")
+        .build();
+
+    let response = client(&server).send(&group(), &message).await.unwrap();
+    assert_eq!(
+        response.name,
+        "projects/1234/messages/0:1500415314455276%31bd1c9631bd1c96"
+    );
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, SEND_PATH);
+    assert_eq!(requests[0].header("authorization"), Some("Bearer token"));
+    assert_eq!(
+        requests[0].json(),
+        json!({
+            "message": {
+                "token": "notification-key",
+                "notification": {"title": "Hello"},
+                "data": {"door": "front"},
+            }
+        })
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn parses_v1_errors() {
+    let server = StubServer::sequence(vec![
+        Reply::json(
+            400,
+            json!({"error": {"code": 400, "message": "Invalid registration", "status": "INVALID_ARGUMENT"}}),
+        ),
+        Reply::json(
+            404,
+            json!({"error": {"code": 404, "message": "Requested entity was not found.", "status": "NOT_FOUND"}}),
+        ),
+    ])
+    .await;
+    let client = client(&server);
+
+    let error = client
+        .send(&group(), &Message::default())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, FCMDeviceGroupsRequestError::BadRequestError(bad_request) if bad_request.error == "Invalid registration"),
+        "{error:?}"
+    );
+
+    let error = client
+        .send(&group(), &Message::default())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, FCMDeviceGroupsRequestError::ErrorResponse(response)
+            if response.status == 404 && response.error.as_deref() == Some("Requested entity was not found.")),
+        "{error:?}"
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn only_retries_sends_fcm_did_not_process() {
+    let server = StubServer::sequence(vec![Reply::status(500)]).await;
+    client(&server)
+        .send(&group(), &Message::default())
+        .await
+        .unwrap_err();
+    assert_eq!(server.requests().len(), 1);
+
+    let server = StubServer::sequence(vec![
+        Reply::status(503),
+        Reply::json(200, json!({"name": "projects/1234/messages/1"})),
+    ])
+    .await;
+    client(&server)
+        .send(&group(), &Message::default())
+        .await
+        .unwrap();
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn clients_without_a_sender_id_need_a_send_url() {
+    let client = FCMDeviceGroupClient::with_client(
+        reqwest::Client::new(),
+        "http://127.0.0.1:1/fcm/notification",
+        NoToken,
+    )
+    .unwrap();
+    let error = client
+        .send(&group(), &Message::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, FCMDeviceGroupsRequestError::MissingSendUrl));
+}
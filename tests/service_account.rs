@@ -0,0 +1,161 @@
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use common::{Reply, StubServer};
+use fcm_device_group::{
+    GetToken,
+    auth::{ServiceAccountAuth, ServiceAccountKey},
+};
+use reqwest::Url;
+use ring::signature::{KeyPair, RSA_PKCS1_2048_8192_SHA256, RsaKeyPair, UnparsedPublicKey};
+use serde_json::{Value, json};
+
+/// A throwaway key generated for these tests
+const KEY_FILE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/data/service_account.json"
+);
+
+fn key() -> ServiceAccountKey {
+    ServiceAccountKey::from_file(KEY_FILE).unwrap()
+}
+
+async fn token_server() -> StubServer {
+    StubServer::start(|_| {
+        Reply::json(
+            200,
+            json!({"access_token": "access-token", "expires_in": 3600, "token_type": "Bearer"}),
+        )
+    })
+    .await
+}
+
+/// Form fields of a request body
+fn form(body: &str) -> Vec<(String, String)> {
+    Url::parse(&format!("http://form/?{body}"))
+        .unwrap()
+        .query_pairs()
+        .into_owned()
+        .collect()
+}
+
+fn decode_json(part: &str) -> Value {
+    serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).unwrap()).unwrap()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn exchanges_a_signed_assertion_for_a_token() {
+    let server = token_server().await;
+    let token_uri = server.url("/token");
+    let auth = ServiceAccountAuth::new(key())
+        .unwrap()
+        .with_token_uri(token_uri.as_str())
+        .unwrap();
+
+    let before = now();
+    let token = auth.get_token(&["scope-a", "scope-b"]).await.unwrap();
+    assert_eq!(token.as_deref(), Some("access-token"));
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].path, "/token");
+    let form = form(&requests[0].body);
+    assert_eq!(
+        form[0],
+        (
+            "grant_type".to_string(),
+            "urn:ietf:params:oauth:grant-type:jwt-bearer".to_string()
+        )
+    );
+    assert_eq!(form[1].0, "assertion");
+
+    let parts: Vec<&str> = form[1].1.split('.').collect();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(
+        decode_json(parts[0]),
+        json!({"alg": "RS256", "typ": "JWT", "kid": "test-key-id"})
+    );
+    let claims = decode_json(parts[1]);
+    assert_eq!(claims["iss"], "test@test-project.iam.gserviceaccount.com");
+    assert_eq!(claims["scope"], "scope-a scope-b");
+    assert_eq!(claims["aud"], token_uri);
+    let iat = claims["iat"].as_u64().unwrap();
+    assert!((before..=now()).contains(&iat));
+    assert_eq!(claims["exp"].as_u64().unwrap(), iat + 3600);
+
+    // The signature verifies with the key's public key
+    let pem = key().private_key;
+    let der = base64::engine::general_purpose::STANDARD
+        .decode(
+            pem.lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect::<String>(),
+        )
+        .unwrap();
+    let key_pair = RsaKeyPair::from_pkcs8(&der).unwrap();
+    let public_key = UnparsedPublicKey::new(&RSA_PKCS1_2048_8192_SHA256, key_pair.public_key());
+    let message = format!("{}.{}", parts[0], parts[1]);
+    public_key
+        .verify(
+            message.as_bytes(),
+            &URL_SAFE_NO_PAD.decode(parts[2]).unwrap(),
+        )
+        .unwrap();
+    assert!(
+        public_key
+            .verify(b"tampered", &URL_SAFE_NO_PAD.decode(parts[2]).unwrap())
+            .is_err()
+    );
+
+    // The token is cached
+    auth.get_token(&["scope-a", "scope-b"]).await.unwrap();
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn omits_kid_without_a_key_id() {
+    let server = token_server().await;
+    let mut key = key();
+    key.private_key_id = None;
+    key.token_uri = server.url("/token");
+    let auth = ServiceAccountAuth::new(key).unwrap();
+
+    auth.get_token(&["scope"]).await.unwrap();
+    let form = form(&server.requests()[0].body);
+    let header = form[1].1.split('.').next().unwrap();
+    assert_eq!(decode_json(header), json!({"alg": "RS256", "typ": "JWT"}));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn reports_token_errors() {
+    let server = StubServer::start(|_| Reply::json(400, json!({"error": "invalid_grant"}))).await;
+    let auth = ServiceAccountAuth::new(key())
+        .unwrap()
+        .with_token_uri(server.url("/token").as_str())
+        .unwrap();
+
+    let error = auth.get_token(&["scope"]).await.unwrap_err();
+    assert!(error.to_string().contains("400"), "{error}");
+}
+
+#[test]
+fn debug_hides_the_private_key() {
+    let key = key();
+    let debug = format!("{key:?}");
+    assert!(debug.contains("test@test-project.iam.gserviceaccount.com"));
+    assert!(!debug.contains("PRIVATE KEY"));
+    assert_eq!(
+        ServiceAccountAuth::new(key).unwrap().project_id(),
+        Some("test-project")
+    );
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::{Reply, StubServer, start, tokens};
+use fcm_device_group::{
+    FCMDeviceGroupClient, NotificationKeyName, RegistrationToken,
+    error::{EnsureGroupError, FCMDeviceGroupsRequestError, ShardedGroupError},
+    sharding::{MAX_GROUP_MEMBERS, ShardedGroup},
+    types::MAX_NOTIFICATION_KEY_NAME_LEN,
+};
+use serde_json::json;
+
+fn shard_members(group: &ShardedGroup) -> Vec<Vec<RegistrationToken>> {
+    group
+        .shards()
+        .iter()
+        .map(|shard| shard.registration_ids.iter().cloned().collect())
+        .collect()
+}
+
+fn sharded(size: usize) -> ShardedGroup {
+    ShardedGroup::new(NotificationKeyName::new("users").unwrap()).with_shard_size(size)
+}
+
+#[test]
+fn shard_names() {
+    let group = ShardedGroup::new(NotificationKeyName::new("users").unwrap());
+    assert_eq!(group.shard_name(0).unwrap(), "users#0");
+    assert_eq!(group.shard_name(12).unwrap(), "users#12");
+
+    // Room for single digit suffixes only
+    let long = "a".repeat(MAX_NOTIFICATION_KEY_NAME_LEN - 2);
+    let group = ShardedGroup::new(NotificationKeyName::new(long).unwrap());
+    assert!(group.shard_name(9).is_ok());
+    assert!(group.shard_name(10).is_err());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn shards_default_to_the_group_limit() {
+    let (server, client) = start().await;
+    let ids: Vec<RegistrationToken> = (0..MAX_GROUP_MEMBERS + 1)
+        .map(|i| RegistrationToken::new(format!("token-{i:02}")).unwrap())
+        .collect();
+    let mut group = ShardedGroup::new(NotificationKeyName::new("users").unwrap());
+    client.add_to_sharded_group(&mut group, ids).await.unwrap();
+
+    assert_eq!(group.shards().len(), 2);
+    assert_eq!(
+        server.group("users#0").unwrap().registration_ids.len(),
+        MAX_GROUP_MEMBERS
+    );
+    assert_eq!(server.group("users#1").unwrap().registration_ids.len(), 1);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn overflow_goes_to_new_shards() {
+    let (server, client) = start().await;
+    let mut group = sharded(2);
+    client
+        .add_to_sharded_group(&mut group, tokens(&["a", "b", "c", "d", "e"]))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        shard_members(&group),
+        [tokens(&["a", "b"]), tokens(&["c", "d"]), tokens(&["e"])]
+    );
+    assert_eq!(group.notification_keys().count(), 3);
+    for (index, shard) in group.shards().iter().enumerate() {
+        let fake = server.group(&format!("users#{index}")).unwrap();
+        assert_eq!(fake.registration_ids, shard.registration_ids);
+        assert_eq!(
+            shard.notification_key.as_ref(),
+            fake.notification_keys.last()
+        );
+    }
+
+    // Existing members are skipped and the last shard is filled first
+    client
+        .add_to_sharded_group(&mut group, tokens(&["a", "f", "g"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        shard_members(&group),
+        [
+            tokens(&["a", "b"]),
+            tokens(&["c", "d"]),
+            tokens(&["e", "f"]),
+            tokens(&["g"])
+        ]
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn emptied_shards_are_recreated_when_refilled() {
+    let (server, client) = start().await;
+    let mut group = sharded(2);
+    client
+        .add_to_sharded_group(&mut group, tokens(&["a", "b", "c"]))
+        .await
+        .unwrap();
+
+    client
+        .remove_from_sharded_group(&mut group, tokens(&["a", "b", "unknown"]))
+        .await
+        .unwrap();
+    assert_eq!(group.shards()[0].notification_key, None);
+    assert!(server.group("users#0").is_none());
+    assert_eq!(
+        group.registration_ids().collect::<Vec<_>>(),
+        [&tokens(&["c"])[0]]
+    );
+
+    client
+        .add_to_sharded_group(&mut group, tokens(&["d"]))
+        .await
+        .unwrap();
+    assert_eq!(shard_members(&group), [tokens(&["d"]), tokens(&["c"])]);
+    assert!(group.shards()[0].notification_key.is_some());
+    assert!(server.group("users#0").is_some());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn too_long_shard_names_fail() {
+    let (server, client) = start().await;
+    let long = "a".repeat(MAX_NOTIFICATION_KEY_NAME_LEN - 2);
+    let mut group = ShardedGroup::new(NotificationKeyName::new(long).unwrap()).with_shard_size(1);
+    let ids: Vec<RegistrationToken> = (0..11)
+        .map(|i| RegistrationToken::new(format!("token-{i:02}")).unwrap())
+        .collect();
+
+    let error = client
+        .add_to_sharded_group(&mut group, ids)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ShardedGroupError::InvalidShardName(_)));
+    // Shards before the one with the invalid name were still created
+    assert_eq!(group.shards().len(), 10);
+    assert_eq!(server.groups().len(), 10);
+}
+
+#[test]
+fn shard_sizes_stay_within_the_group_limit() {
+    let group = sharded(50);
+    let stored = serde_json::to_value(&group).unwrap();
+    assert_eq!(stored["shard_size"], MAX_GROUP_MEMBERS);
+    assert_eq!(
+        serde_json::from_value::<ShardedGroup>(stored).unwrap(),
+        group
+    );
+
+    for shard_size in [0, MAX_GROUP_MEMBERS + 1] {
+        let stored = json!({"name": "users", "shard_size": shard_size, "shards": []});
+        assert!(serde_json::from_value::<ShardedGroup>(stored).is_err());
+    }
+}
+
+/// A client splitting requests into batches of 2, against replies giving each request a new key
+async fn batching_client(replies: Vec<Reply>) -> (StubServer, FCMDeviceGroupClient) {
+    let server = StubServer::sequence(replies).await;
+    let client = server.client().with_max_registration_ids_per_request(2);
+    (server, client)
+}
+
+fn key(key: &str) -> Reply {
+    Reply::json(200, json!({"notification_key": key}))
+}
+
+fn rejected() -> Reply {
+    Reply::json(400, json!({"error": "no valid registration ids"}))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn batches_applied_before_a_failure_are_recorded() {
+    // Creating the shard: the first two batches succeed and the third fails
+    let (_server, client) = batching_client(vec![key("key-1"), key("key-2"), rejected()]).await;
+    let mut group = sharded(10);
+    let error = client
+        .add_to_sharded_group(&mut group, tokens(&["a", "b", "c", "d", "e"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ShardedGroupError::Ensure(EnsureGroupError::Create(
+            FCMDeviceGroupsRequestError::PartialBatchFailure(_)
+        ))
+    ));
+    assert_eq!(shard_members(&group), [tokens(&["a", "b", "c", "d"])]);
+    assert_eq!(
+        group.shards()[0].notification_key.as_ref().unwrap(),
+        "key-2"
+    );
+
+    // Adding to the existing shard
+    let (_server, client) = batching_client(vec![key("key-3"), rejected()]).await;
+    let error = client
+        .add_to_sharded_group(&mut group, tokens(&["f", "g", "h"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ShardedGroupError::ChangeMembers(FCMDeviceGroupsRequestError::PartialBatchFailure(_))
+    ));
+    assert_eq!(
+        shard_members(&group),
+        [tokens(&["a", "b", "c", "d", "f", "g"])]
+    );
+    assert_eq!(
+        group.shards()[0].notification_key.as_ref().unwrap(),
+        "key-3"
+    );
+
+    // Removing from it
+    let (_server, client) = batching_client(vec![key("key-4"), rejected()]).await;
+    let error = client
+        .remove_from_sharded_group(&mut group, tokens(&["a", "b", "c", "d"]))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ShardedGroupError::ChangeMembers(FCMDeviceGroupsRequestError::PartialBatchFailure(_))
+    ));
+    assert_eq!(shard_members(&group), [tokens(&["c", "d", "f", "g"])]);
+    assert_eq!(
+        group.shards()[0].notification_key.as_ref().unwrap(),
+        "key-4"
+    );
+}
@@ -0,0 +1,127 @@
+mod common;
+
+use common::{Reply, StubServer};
+use fcm_device_group::{
+    RegistrationToken,
+    error::{FCMDeviceGroupsRequestError, TopicBatchError},
+    topic::{FCMTopicClient, MAX_TOKENS_PER_TOPIC_REQUEST, TopicError},
+};
+use reqwest::Url;
+use serde_json::json;
+
+fn topics(server: &StubServer) -> FCMTopicClient {
+    server
+        .client()
+        .topics()
+        .with_url(Url::parse(&server.url("/iid/v1")).unwrap())
+}
+
+fn tokens(count: usize) -> Vec<RegistrationToken> {
+    (0..count)
+        .map(|i| RegistrationToken::new(format!("token-{i}")).unwrap())
+        .collect()
+}
+
+/// Answer a batch request with one successful result per token
+fn all_succeeded(request: &common::Request) -> Reply {
+    let count = request.json()["registration_tokens"]
+        .as_array()
+        .unwrap()
+        .len();
+    Reply::json(200, json!({"results": vec![json!({}); count]}))
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn subscribe_maps_results_to_tokens() {
+    let server = StubServer::sequence(vec![Reply::json(
+        200,
+        json!({"results": [{}, {"error": "NOT_FOUND"}, {"error": "SOMETHING_NEW"}]}),
+    )])
+    .await;
+    let results = topics(&server).subscribe("news", tokens(3)).await.unwrap();
+
+    let errors: Vec<_> = results.iter().map(|result| result.error.clone()).collect();
+    assert_eq!(
+        errors,
+        [
+            None,
+            Some(TopicError::NotFound),
+            Some(TopicError::Other("SOMETHING_NEW".to_string()))
+        ]
+    );
+    assert_eq!(results[1].registration_token, "token-1");
+
+    let request = &server.requests()[0];
+    assert_eq!(request.path, "/iid/v1:batchAdd");
+    assert_eq!(
+        request.json(),
+        json!({"to": "/topics/news", "registration_tokens": ["token-0", "token-1", "token-2"]})
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn unsubscribe_is_split_into_batches() {
+    let server = StubServer::start(all_succeeded).await;
+    let results = topics(&server)
+        .unsubscribe("/topics/news", tokens(MAX_TOKENS_PER_TOPIC_REQUEST + 1))
+        .await
+        .unwrap();
+    assert_eq!(results.len(), MAX_TOKENS_PER_TOPIC_REQUEST + 1);
+
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].path, "/iid/v1:batchRemove");
+    assert_eq!(requests[1].json()["to"], "/topics/news");
+    assert_eq!(
+        requests[1].json()["registration_tokens"],
+        json!([format!("token-{MAX_TOKENS_PER_TOPIC_REQUEST}")])
+    );
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn result_count_mismatch_is_an_error() {
+    let server = StubServer::sequence(vec![Reply::json(200, json!({"results": [{}]}))]).await;
+    let failure = topics(&server)
+        .subscribe("news", tokens(2))
+        .await
+        .unwrap_err();
+    assert!(failure.applied.is_empty());
+    assert_eq!(failure.failed, tokens(2));
+    assert!(matches!(
+        failure.source,
+        TopicBatchError::ResultCountMismatch {
+            expected: 2,
+            actual: 1
+        }
+    ));
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn failed_batch_keeps_earlier_results() {
+    let server = StubServer::start(|request| {
+        let first = request.json()["registration_tokens"][0].clone();
+        if first == format!("token-{MAX_TOKENS_PER_TOPIC_REQUEST}") {
+            Reply::status(500)
+        } else {
+            all_succeeded(request)
+        }
+    })
+    .await;
+    let all = tokens(MAX_TOKENS_PER_TOPIC_REQUEST * 2 + 1);
+    let failure = topics(&server)
+        .subscribe("news", all.clone())
+        .await
+        .unwrap_err();
+
+    assert_eq!(failure.applied.len(), MAX_TOKENS_PER_TOPIC_REQUEST);
+    assert_eq!(
+        failure.failed,
+        all[MAX_TOKENS_PER_TOPIC_REQUEST..MAX_TOKENS_PER_TOPIC_REQUEST * 2]
+    );
+    assert_eq!(failure.remaining, all[MAX_TOKENS_PER_TOPIC_REQUEST * 2..]);
+    assert!(matches!(
+        failure.source,
+        TopicBatchError::Request(FCMDeviceGroupsRequestError::ErrorResponse(_))
+    ));
+    assert_eq!(server.requests().len(), 2);
+}
@@ -0,0 +1,144 @@
+use fcm_device_group::{
+    FCMDeviceGroup, NotificationKey, NotificationKeyName, RegistrationToken,
+    error::InvalidIdentifierError,
+    types::{MAX_NOTIFICATION_KEY_NAME_LEN, MAX_TOKEN_LEN, reveal_secrets_in_debug},
+};
+
+#[test]
+fn rejects_empty_values() {
+    assert_eq!(
+        NotificationKeyName::new(""),
+        Err(InvalidIdentifierError::Empty {
+            kind: "notification key name"
+        })
+    );
+    assert_eq!(
+        "".parse::<NotificationKey>(),
+        Err(InvalidIdentifierError::Empty {
+            kind: "notification key"
+        })
+    );
+    assert_eq!(
+        RegistrationToken::try_from(""),
+        Err(InvalidIdentifierError::Empty {
+            kind: "registration token"
+        })
+    );
+}
+
+#[test]
+fn rejects_values_that_are_too_long() {
+    assert!(NotificationKeyName::new("a".repeat(MAX_NOTIFICATION_KEY_NAME_LEN)).is_ok());
+    assert_eq!(
+        NotificationKeyName::new("a".repeat(MAX_NOTIFICATION_KEY_NAME_LEN + 1)),
+        Err(InvalidIdentifierError::TooLong {
+            kind: "notification key name",
+            max_len: MAX_NOTIFICATION_KEY_NAME_LEN
+        })
+    );
+    // The limit is in bytes, not characters
+    assert!(NotificationKeyName::new("é".repeat(MAX_NOTIFICATION_KEY_NAME_LEN / 2 + 1)).is_err());
+
+    assert!(RegistrationToken::new("a".repeat(MAX_TOKEN_LEN)).is_ok());
+    assert_eq!(
+        RegistrationToken::new("a".repeat(MAX_TOKEN_LEN + 1)),
+        Err(InvalidIdentifierError::TooLong {
+            kind: "registration token",
+            max_len: MAX_TOKEN_LEN
+        })
+    );
+}
+
+#[test]
+fn validates_characters() {
+    assert!(NotificationKeyName::new("appUser-Chris é#1 / ok").is_ok());
+    assert_eq!(
+        NotificationKeyName::new("line\nbreak"),
+        Err(InvalidIdentifierError::InvalidCharacter {
+            kind: "notification key name",
+            character: '\n'
+        })
+    );
+
+    assert!(RegistrationToken::new("bk3RNwTe3H0:CI2k_HHwgIpoDKCIZvvDMExUdFQ3P1.x-y").is_ok());
+    assert_eq!(
+        RegistrationToken::new("has space"),
+        Err(InvalidIdentifierError::InvalidCharacter {
+            kind: "registration token",
+            character: ' '
+        })
+    );
+    assert_eq!(
+        NotificationKey::new("key/1"),
+        Err(InvalidIdentifierError::InvalidCharacter {
+            kind: "notification key",
+            character: '/'
+        })
+    );
+}
+
+#[test]
+fn debug_redacts_secrets_unless_revealed() {
+    let group = FCMDeviceGroup {
+        notification_key_name: NotificationKeyName::new("group").unwrap(),
+        notification_key: NotificationKey::new("secret-key").unwrap(),
+    };
+    let token = RegistrationToken::new("secret-token").unwrap();
+
+    assert_eq!(format!("{token:?}"), "RegistrationToken(<redacted>)");
+    let debug = format!("{group:?}");
+    assert!(debug.contains("NotificationKeyName(\"group\")"));
+    assert!(debug.contains("NotificationKey(<redacted>)"));
+    assert!(!debug.contains("secret-key"));
+
+    // Display always shows the value
+    assert_eq!(token.to_string(), "secret-token");
+    assert_eq!(group.notification_key.to_string(), "secret-key");
+
+    reveal_secrets_in_debug(true);
+    assert_eq!(format!("{token:?}"), "RegistrationToken(\"secret-token\")");
+    assert!(format!("{group:?}").contains("NotificationKey(\"secret-key\")"));
+    reveal_secrets_in_debug(false);
+    assert_eq!(format!("{token:?}"), "RegistrationToken(<redacted>)");
+}
+
+#[test]
+fn serializes_as_plain_strings() {
+    let tokens = vec![
+        RegistrationToken::new("a").unwrap(),
+        RegistrationToken::new("b").unwrap(),
+    ];
+    let json = serde_json::to_string(&tokens).unwrap();
+    assert_eq!(json, r#"["a","b"]"#);
+    assert_eq!(
+        serde_json::from_str::<Vec<RegistrationToken>>(&json).unwrap(),
+        tokens
+    );
+
+    let key: NotificationKey = serde_json::from_str(r#""key-1""#).unwrap();
+    assert_eq!(key, "key-1");
+}
+
+#[test]
+fn deserializing_validates() {
+    let error = serde_json::from_str::<RegistrationToken>(r#""has space""#).unwrap_err();
+    assert!(error.to_string().contains("invalid character ' '"));
+    assert!(serde_json::from_str::<NotificationKeyName>(r#""""#).is_err());
+}
+
+#[test]
+fn conversions_keep_the_value() {
+    let name = NotificationKeyName::new("group").unwrap();
+    assert_eq!(name.as_str(), "group");
+    assert_eq!(name.as_ref(), "group");
+    assert_eq!(String::from(name.clone()), "group");
+    assert_eq!(name.clone().into_string(), "group");
+    assert_eq!(
+        NotificationKeyName::try_from("group".to_string()).unwrap(),
+        name
+    );
+
+    let set: std::collections::BTreeSet<RegistrationToken> =
+        [RegistrationToken::new("a").unwrap()].into_iter().collect();
+    assert!(set.contains("a"));
+}